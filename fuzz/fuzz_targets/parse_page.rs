@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use scraper::Html;
+
+#[allow(dead_code)]
+#[path = "../../src/evaluations/page.rs"]
+mod page;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(page) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(eval) = page::parse(&Html::parse_document(page), 1, "CSE 120") {
+        assert!(eval.has_valid_shape(), "{eval:?}");
+    }
+});
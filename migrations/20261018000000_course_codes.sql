@@ -0,0 +1,27 @@
+ALTER TABLE courses
+ADD COLUMN subject VARCHAR(20),
+ADD COLUMN number INTEGER,
+ADD COLUMN suffix VARCHAR(20);
+
+UPDATE courses
+SET
+    subject = parsed.m[1],
+    number = parsed.m[2]::INTEGER,
+    suffix = NULLIF(parsed.m[3], '')
+FROM
+    (
+        SELECT
+            code,
+            -- Same grammar as CourseCode::parse, trying the name before the code
+            COALESCE(
+                REGEXP_MATCH(name, '^([A-Za-z]+)\s+([0-9]{1,9})([A-Za-z][A-Za-z0-9]*)?(\s|$)'),
+                REGEXP_MATCH(code, '^([A-Za-z]+)\s+([0-9]{1,9})([A-Za-z][A-Za-z0-9]*)?(\s|$)')
+            ) AS m
+        FROM
+            courses
+    ) AS parsed
+WHERE
+    courses.code = parsed.code
+    AND parsed.m IS NOT NULL;
+
+CREATE INDEX courses_subject_number_idx ON courses (subject, number, suffix);
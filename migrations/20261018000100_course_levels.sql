@@ -0,0 +1,14 @@
+ALTER TABLE courses
+ADD COLUMN level VARCHAR(10) CHECK (level IN ('lower', 'upper', 'graduate'));
+
+UPDATE courses
+SET
+    level = CASE
+        WHEN number < 100 THEN 'lower'
+        WHEN number < 200 THEN 'upper'
+        WHEN number < 600 THEN 'graduate'
+    END
+WHERE
+    number IS NOT NULL;
+
+CREATE INDEX courses_level_idx ON courses (unit_id, level);
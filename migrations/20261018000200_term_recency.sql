@@ -0,0 +1,10 @@
+-- Orders term names such as "Fall 2023" chronologically, e.g. 20234.
+CREATE FUNCTION term_recency (name TEXT) RETURNS INTEGER AS $$
+    SELECT COALESCE(SUBSTRING(name FROM '[0-9]{4}')::INTEGER, 0) * 10 + CASE
+        WHEN name ILIKE 'Winter%' THEN 1
+        WHEN name ILIKE 'Spring%' THEN 2
+        WHEN name ILIKE 'Summer%' THEN 3
+        WHEN name ILIKE 'Fall%' THEN 4
+        ELSE 0
+    END
+$$ LANGUAGE SQL IMMUTABLE;
@@ -0,0 +1,6 @@
+CREATE TABLE
+    cookies (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        expires BIGINT
+    );
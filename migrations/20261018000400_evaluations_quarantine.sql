@@ -0,0 +1,9 @@
+-- Evaluations that failed `evals validate --quarantine`, kept out of the API and the
+-- scrape queue until someone looks at them.
+CREATE TABLE
+    evaluations_quarantine (
+        LIKE evaluations,
+        problems TEXT NOT NULL,
+        quarantined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        PRIMARY KEY (sid)
+    );
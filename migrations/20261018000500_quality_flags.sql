@@ -0,0 +1,19 @@
+ALTER TABLE evaluations
+ADD COLUMN quality_flags TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE evaluations_quarantine
+ADD COLUMN quality_flags TEXT[] NOT NULL DEFAULT '{}';
+
+-- Flags that can be worked out from stored rows, using the thresholds in page.rs.
+-- layout_fallback needs the page itself and is only set once a section is refreshed.
+UPDATE evaluations
+SET
+    quality_flags = ARRAY_REMOVE(
+        ARRAY[
+            CASE WHEN CARDINALITY(expected_grades) = 0 THEN 'missing_expected_grades' END,
+            CASE WHEN responses < enrollment * 0.25 THEN 'low_response_rate' END,
+            CASE WHEN responses < 5 THEN 'few_responses' END,
+            CASE WHEN CARDINALITY(hours) = 4 THEN 'coarse_hours' END
+        ],
+        NULL
+    );
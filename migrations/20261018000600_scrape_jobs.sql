@@ -0,0 +1,19 @@
+-- Sections waiting to be scraped by `worker` processes. A worker leases a batch by
+-- setting leased_by and leased_until, and keeps extending the lease while it works. Jobs
+-- whose lease runs out go back to the queue. Failed jobs keep a leased_until without a
+-- leased_by, which holds them back until it passes.
+CREATE TABLE
+    scrape_jobs (
+        sid INTEGER PRIMARY KEY REFERENCES sids (sid),
+        priority INTEGER NOT NULL DEFAULT 0,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        leased_by TEXT,
+        leased_until TIMESTAMPTZ,
+        last_error TEXT,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        done_at TIMESTAMPTZ
+    );
+
+CREATE INDEX scrape_jobs_ready ON scrape_jobs (priority DESC, sid)
+WHERE
+    done_at IS NULL;
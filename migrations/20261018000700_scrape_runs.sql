@@ -0,0 +1,11 @@
+-- One row per pipeline stage the `serve` scheduler has run. succeeded stays NULL while
+-- the stage is running, or if the server stopped before it finished.
+CREATE TABLE
+    scrape_runs (
+        id SERIAL PRIMARY KEY,
+        stage TEXT NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        finished_at TIMESTAMPTZ,
+        succeeded BOOLEAN,
+        detail TEXT
+    );
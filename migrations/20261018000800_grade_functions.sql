@@ -0,0 +1,15 @@
+-- Mean grade points of a grade distribution (A, B, C, D, F, ...), or NULL without grades.
+CREATE FUNCTION grade_gpa (grades INTEGER[]) RETURNS FLOAT8 AS $$
+    SELECT (SUM(n * w) / NULLIF(SUM(CASE WHEN i <= 5 THEN n ELSE 0 END), 0))::FLOAT8
+    FROM UNNEST(grades, ARRAY[4.0, 3.0, 2.0, 1.0]) WITH ORDINALITY AS arr (n, w, i)
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Mean weekly study hours of an hours distribution, taking the middle of each bucket.
+-- Older pages use four coarse buckets instead of eleven.
+CREATE FUNCTION mean_hours (hours INTEGER[]) RETURNS FLOAT8 AS $$
+    SELECT (SUM(n * w) / NULLIF(SUM(n), 0))::FLOAT8
+    FROM UNNEST(hours, CASE
+        WHEN CARDINALITY(hours) = 4 THEN ARRAY[0.0, 5.0, 10.0, 15.0]
+        ELSE ARRAY[1.0, 3.0, 5.0, 7.0, 9.0, 11.0, 13.0, 15.0, 17.0, 19.0, 21.0]
+    END) WITH ORDINALITY AS arr (n, w, i)
+$$ LANGUAGE SQL IMMUTABLE;
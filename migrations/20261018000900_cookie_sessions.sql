@@ -0,0 +1,9 @@
+-- Cookies are kept per `[[sessions]]` entry, with the top-level session's under "default".
+ALTER TABLE cookies
+ADD COLUMN session TEXT NOT NULL DEFAULT 'default';
+
+ALTER TABLE cookies
+DROP CONSTRAINT cookies_pkey;
+
+ALTER TABLE cookies
+ADD PRIMARY KEY (session, name);
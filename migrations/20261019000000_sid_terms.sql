@@ -0,0 +1,5 @@
+-- The term each section is from, as listed in the search results, so pending sections can
+-- be filtered by term before they are scraped. Sections found before this are NULL until
+-- `evals sids` runs again.
+ALTER TABLE sids
+ADD COLUMN term TEXT;
@@ -1,14 +1,17 @@
-use crate::api::internal_error;
+use crate::api::{float_as_str, internal_error};
+use crate::courses::CourseLevel;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::routing::get;
 use axum::{Json, Router};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::{query_as, Pool, Postgres};
+use sqlx::{query, query_as, Pool, Postgres};
 
 pub fn get_router() -> Router<Pool<Postgres>> {
-    Router::new().route("/", get(search))
+    Router::new()
+        .route("/", get(search))
+        .route("/levels", get(levels))
 }
 
 #[derive(Deserialize)]
@@ -16,6 +19,8 @@ struct SearchQuery {
     page: Option<i32>,
     per_page: Option<i32>,
     q: Option<String>,
+    subject: Option<String>,
+    level: Option<CourseLevel>,
 }
 
 #[derive(Serialize)]
@@ -31,15 +36,19 @@ async fn search(
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     let per_page = args.per_page.unwrap_or(20).min(100);
     let like = format!("%{}%", args.q.clone().unwrap_or_default());
-    let res = query_as!(SearchResult,
+    let res = query_as!(
+        SearchResult,
         "
             SELECT courses.code, courses.name, units.name as unit FROM courses
             INNER JOIN units ON courses.unit_id = units.id
             WHERE courses.name ILIKE $1
+            AND ($4::text IS NULL OR courses.subject ILIKE $4)
+            AND ($5::text IS NULL OR courses.level = $5)
             ORDER BY
-                SPLIT_PART(courses.name, ' ', 1),
-                CAST(REGEXP_REPLACE(SPLIT_PART(courses.name, ' ', 2), '[A-Za-z]+$', '') AS INTEGER),
-                NULLIF(REGEXP_REPLACE(SPLIT_PART(courses.name, ' ', 2), '^[0-9]+', ''), '') NULLS FIRST
+                courses.subject NULLS LAST,
+                courses.number NULLS LAST,
+                courses.suffix NULLS FIRST,
+                courses.name
             OFFSET $2 LIMIT $3
         ",
         like,
@@ -47,6 +56,8 @@ async fn search(
             .map(|page| (page.max(1) - 1) * per_page)
             .unwrap_or_default() as i32,
         per_page as i32,
+        args.subject,
+        args.level.map(|level| level.as_str()),
     )
     .fetch_all(&pool)
     .await
@@ -54,3 +65,103 @@ async fn search(
 
     Ok(Json(json!(res)))
 }
+
+#[derive(Deserialize)]
+struct LevelsQuery {
+    unit: Option<i32>,
+    subject: Option<String>,
+    level: Option<CourseLevel>,
+}
+
+#[derive(Serialize)]
+struct LevelSummary {
+    unit_id: i32,
+    unit: String,
+    level: Option<String>,
+    courses: i64,
+    sections: i64,
+    #[serde(rename = "actualGPA", serialize_with = "float_as_str")]
+    actual_gpa: f64,
+    #[serde(rename = "expectedGPA", serialize_with = "float_as_str")]
+    expected_gpa: f64,
+    #[serde(serialize_with = "float_as_str")]
+    hours: f64,
+}
+
+async fn levels(
+    State(pool): State<Pool<Postgres>>,
+    args: Query<LevelsQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let res = query!(
+        "
+            WITH stats AS (
+                SELECT
+                    courses.unit_id,
+                    courses.level,
+                    courses.code,
+                    grade_gpa(actual_grades) AS actual_gpa,
+                    grade_gpa(expected_grades) AS expected_gpa,
+                    mean_hours(hours) AS hours
+                FROM evaluations
+                INNER JOIN courses ON evaluations.course_code = courses.code
+                WHERE evaluations.sid NOT IN (SELECT sid FROM evaluations_quarantine)
+                AND ($1::int IS NULL OR courses.unit_id = $1)
+                AND ($2::text IS NULL OR courses.subject ILIKE $2)
+                AND ($3::text IS NULL OR courses.level = $3)
+            )
+
+            SELECT
+                units.id AS unit_id,
+                units.name AS unit,
+                stats.level,
+                COUNT(DISTINCT stats.code) AS \"courses!: i64\",
+                COUNT(*) AS \"sections!: i64\",
+                COALESCE(AVG(actual_gpa), -1.0) AS \"actual_gpa!: f64\",
+                COALESCE(AVG(expected_gpa), -1.0) AS \"expected_gpa!: f64\",
+                COALESCE(AVG(hours), -1.0) AS \"hours!: f64\"
+            FROM stats
+            INNER JOIN units ON stats.unit_id = units.id
+            GROUP BY units.id, units.name, stats.level
+            ORDER BY
+                units.name,
+                CASE stats.level WHEN 'lower' THEN 1 WHEN 'upper' THEN 2 WHEN 'graduate' THEN 3 END
+        ",
+        args.unit,
+        args.subject,
+        args.level.map(|level| level.as_str()),
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(internal_error)?
+    .into_iter()
+    .map(|row| LevelSummary {
+        unit_id: row.unit_id,
+        unit: row.unit,
+        level: row.level,
+        courses: row.courses,
+        sections: row.sections,
+        actual_gpa: row.actual_gpa,
+        expected_gpa: row.expected_gpa,
+        hours: row.hours,
+    })
+    .collect::<Vec<_>>();
+
+    Ok(Json(json!(res)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(fixtures("evaluations"))]
+    async fn levels_leave_out_quarantined_sections(pool: Pool<Postgres>) {
+        let query = LevelsQuery {
+            unit: None,
+            subject: None,
+            level: None,
+        };
+        let Json(res) = levels(State(pool), Query(query)).await.unwrap();
+        assert_eq!(res[0]["courses"], 1);
+        assert_eq!(res[0]["sections"], 2);
+    }
+}
@@ -1,13 +1,13 @@
-use crate::api::internal_error;
+use crate::api::{float_as_str, internal_error};
 use crate::database::Instructor;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::routing::get;
 use axum::{Json, Router};
-use serde::{Serialize, Serializer};
+use serde::Serialize;
 use serde_json::json;
 use sqlx::{query, query_as, Pool, Postgres};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 pub fn get_router() -> Router<Pool<Postgres>> {
     Router::new()
@@ -27,6 +27,7 @@ async fn instructors(
             SELECT DISTINCT id, name FROM instructors
             INNER JOIN evaluations ON evaluations.instructor_id = instructors.id
             WHERE evaluations.course_code ILIKE $1
+            AND evaluations.sid NOT IN (SELECT sid FROM evaluations_quarantine)
         ",
         code
     )
@@ -41,7 +42,11 @@ async fn list_evals(
     State(pool): State<Pool<Postgres>>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
     let res = query!(
-        "SELECT sid FROM evaluations WHERE course_code ILIKE $1",
+        "
+            SELECT sid FROM evaluations
+            WHERE course_code ILIKE $1
+            AND sid NOT IN (SELECT sid FROM evaluations_quarantine)
+        ",
         code
     )
     .fetch_all(&pool)
@@ -63,48 +68,32 @@ struct Summary {
     expected_gpa: f64,
     #[serde(serialize_with = "float_as_str")]
     hours: f64,
-}
-
-fn float_as_str<S>(x: &f64, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let rounded = format!("{:.2}", x);
-    s.serialize_str(&rounded)
+    /// How many of the sections carry each quality flag
+    #[serde(rename = "qualityFlags")]
+    quality_flags: BTreeMap<String, i64>,
 }
 
 async fn summary(
     Path(code): Path<String>,
     State(pool): State<Pool<Postgres>>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let res = query!(
+    let rows = query!(
         "
             WITH stats AS (
                 SELECT
                     instructors.id,
                     instructors.name,
-                    (
-                        SELECT (SUM(n * w) / NULLIF(SUM(CASE WHEN i <= 5 THEN n ELSE 0 END), 0))::float8
-                        FROM UNNEST(actual_grades, array[4.0, 3.0, 2.0, 1.0]) WITH ORDINALITY AS arr(n, w, i)
-                    ) AS actual_gpa,
-                    (
-                        SELECT (SUM(n * w) / NULLIF(SUM(CASE WHEN i <= 5 THEN n ELSE 0 END), 0))::float8
-                        FROM UNNEST(expected_grades, array[4.0, 3.0, 2.0, 1.0]) WITH ORDINALITY AS arr(n, w, i)
-                    ) AS expected_gpa,
-                    (
-                        SELECT (SUM(n * w) / NULLIF(SUM(n), 0))::float8
-                        FROM UNNEST(hours, CASE
-                            WHEN CARDINALITY(hours) = 4 THEN array[0.0, 5.0, 10.0, 15.0]
-                            ELSE array[1.0, 3.0, 5.0, 7.0, 9.0, 11.0, 13.0, 15.0, 17.0, 19.0, 21.0]
-                        END)
-                        WITH ORDINALITY AS arr(n, w, i)
-                    ) AS hours
+                    grade_gpa(actual_grades) AS actual_gpa,
+                    grade_gpa(expected_grades) AS expected_gpa,
+                    mean_hours(hours) AS hours
                 FROM evaluations
                 INNER JOIN instructors ON evaluations.instructor_id = instructors.id
                 WHERE course_code = $1
+                AND sid NOT IN (SELECT sid FROM evaluations_quarantine)
             )
 
             SELECT
+                id AS \"id?\",
                 name as \"instructor!\",
                 COUNT(*) AS \"sections!: i64\",
                 COALESCE(AVG(actual_gpa), -1.0) AS \"actual_gpa!: f64\",
@@ -116,6 +105,7 @@ async fn summary(
             UNION ALL
 
             SELECT
+                NULL,
                 'overall' as \"instructor!\",
                 COUNT(*) AS \"sections!: i64\",
                 COALESCE(AVG(actual_gpa), -1.0) AS \"actual_gpa!: f64\",
@@ -124,18 +114,59 @@ async fn summary(
             FROM stats
         ",
         code
-    ).fetch_all(&pool).await.map_err(internal_error)?
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(internal_error)?;
+
+    // Keyed by instructor id, with `None` for the overall row, so that an instructor who
+    // happens to be called "overall" doesn't share its flags
+    let mut res = rows
         .into_iter()
         .map(|row| {
-            (row.instructor, Summary {
-                sections: row.sections,
-                actual_gpa: row.actual_gpa,
-                expected_gpa: row.expected_gpa,
-                hours: row.hours,
-            })
+            (
+                row.id,
+                (
+                    row.instructor,
+                    Summary {
+                        sections: row.sections,
+                        actual_gpa: row.actual_gpa,
+                        expected_gpa: row.expected_gpa,
+                        hours: row.hours,
+                        quality_flags: BTreeMap::new(),
+                    },
+                ),
+            )
         })
         .collect::<HashMap<_, _>>();
 
+    let flagged = query!(
+        "
+            SELECT instructor_id, quality_flags
+            FROM evaluations
+            WHERE course_code = $1 AND CARDINALITY(quality_flags) > 0
+            AND sid NOT IN (SELECT sid FROM evaluations_quarantine)
+        ",
+        code
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(internal_error)?;
+    for row in flagged {
+        for flag in row.quality_flags {
+            for key in [Some(row.instructor_id), None] {
+                if let Some((_, summary)) = res.get_mut(&key) {
+                    *summary.quality_flags.entry(flag.clone()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    // The overall row goes in last, so it's the one that is kept if an instructor shares
+    // its name
+    let overall = res.remove(&None);
+    let res = res.into_values().chain(overall).collect::<HashMap<_, _>>();
+
     Ok(Json(json!(res)))
 }
 
@@ -143,31 +174,85 @@ async fn eval_summary(
     Path(sid): Path<i32>,
     State(pool): State<Pool<Postgres>>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let res = query_as!(Summary,
+    let row = query!(
         "
             SELECT
                 1 AS \"sections!: i64\",
-                COALESCE((
-                    SELECT (SUM(n * w) / NULLIF(SUM(CASE WHEN i <= 5 THEN n ELSE 0 END), 0))::float8
-                    FROM UNNEST(actual_grades, array[4.0, 3.0, 2.0, 1.0]) WITH ORDINALITY AS arr(n, w, i)
-                ), -1.0) AS \"actual_gpa!: f64\",
-                COALESCE((
-                    SELECT (SUM(n * w) / NULLIF(SUM(CASE WHEN i <= 5 THEN n ELSE 0 END), 0))::float8
-                    FROM UNNEST(expected_grades, array[4.0, 3.0, 2.0, 1.0]) WITH ORDINALITY AS arr(n, w, i)
-                ), -1.0) AS \"expected_gpa!: f64\",
-                COALESCE((
-                    SELECT (SUM(n * w) / NULLIF(SUM(n), 0))::float8
-                    FROM UNNEST(hours, CASE
-                        WHEN CARDINALITY(hours) = 4 THEN array[0.0, 5.0, 10.0, 15.0]
-                        ELSE array[1.0, 3.0, 5.0, 7.0, 9.0, 11.0, 13.0, 15.0, 17.0, 19.0, 21.0]
-                    END)
-                    WITH ORDINALITY AS arr(n, w, i)
-                ), -1.0) AS \"hours!: f64\"
+                COALESCE(grade_gpa(actual_grades), -1.0) AS \"actual_gpa!: f64\",
+                COALESCE(grade_gpa(expected_grades), -1.0) AS \"expected_gpa!: f64\",
+                COALESCE(mean_hours(hours), -1.0) AS \"hours!: f64\",
+                quality_flags
             FROM evaluations
             WHERE sid = $1
+            AND sid NOT IN (SELECT sid FROM evaluations_quarantine)
         ",
         sid
-    ).fetch_one(&pool).await.map_err(internal_error)?;
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(internal_error)?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        format!("No evaluation for section {sid}"),
+    ))?;
+
+    let res = Summary {
+        sections: row.sections,
+        actual_gpa: row.actual_gpa,
+        expected_gpa: row.expected_gpa,
+        hours: row.hours,
+        quality_flags: row
+            .quality_flags
+            .into_iter()
+            .map(|flag| (flag, 1))
+            .collect(),
+    };
 
     Ok(Json(json!(res)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test(fixtures("evaluations"))]
+    async fn quarantined_sections_are_left_out(pool: Pool<Postgres>) {
+        let Json(sections) = list_evals(Path("CSE 120".to_string()), State(pool.clone()))
+            .await
+            .unwrap();
+        let mut sections = serde_json::from_value::<Vec<i32>>(sections).unwrap();
+        sections.sort();
+        assert_eq!(sections, [1, 2]);
+
+        let Json(res) = summary(Path("CSE 120".to_string()), State(pool.clone()))
+            .await
+            .unwrap();
+        assert_eq!(res["Doe, Jane"]["sections"], 1);
+
+        assert!(eval_summary(Path(1), State(pool.clone())).await.is_ok());
+        for sid in [3, 4, 5] {
+            let (status, _) = eval_summary(Path(sid), State(pool.clone()))
+                .await
+                .unwrap_err();
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test(fixtures("evaluations"))]
+    async fn flags_stay_with_their_instructor(pool: Pool<Postgres>) {
+        let Json(res) = summary(Path("CSE 120".to_string()), State(pool))
+            .await
+            .unwrap();
+        assert_eq!(
+            res["Doe, Jane"]["qualityFlags"],
+            json!({ "coarse_hours": 1 })
+        );
+        // The instructor called "overall" is shadowed by the totals, but its flags are
+        // only counted once in them. The quarantined section and its flag count nowhere.
+        assert_eq!(res["overall"]["sections"], 2);
+        assert_eq!(
+            res["overall"]["qualityFlags"],
+            json!({ "coarse_hours": 1, "few_responses": 1 })
+        );
+    }
+}
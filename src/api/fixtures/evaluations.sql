@@ -0,0 +1,43 @@
+-- Three CSE 120 sections, one of them quarantined after being scraped again, plus a
+-- quarantined section that is only in quarantine. One instructor is called "overall", and
+-- only the quarantined section is flagged low_response_rate.
+INSERT INTO units (id, name) VALUES (1, 'Computer Science and Engineering');
+INSERT INTO courses (code, name, unit_id)
+VALUES ('CSE 120', 'CSE 120 - Principles of Computer Operating Systems', 1);
+INSERT INTO terms (id, name) VALUES (1, 'Fall 2023');
+INSERT INTO instructors (id, name) VALUES (1, 'Doe, Jane'), (2, 'overall');
+
+INSERT INTO evaluations (
+    sid, section_name, course_code, term_id, instructor_id, enrollment, responses,
+    class_helped_understanding, assignments_helped_understanding, fair_exams,
+    timely_feedback, developed_understanding, engaging, communication, help_opportunities,
+    effective_methods, timeliness, welcoming, materials, hours, expected_grades,
+    actual_grades, quality_flags
+)
+SELECT
+    sid, section_name, 'CSE 120', 1, instructor_id, 30, 20,
+    scale, scale, scale, scale, scale, scale, scale, scale, scale, scale, scale,
+    '{5,4,1,3,7}', '{5,6,5,4}', '{2,5,0,5,3,3,2}', '{4,7,4,1,5,4,5}', quality_flags
+FROM (
+    VALUES
+        (1, 'A00', 1, '{coarse_hours}'::TEXT[]),
+        (2, 'B00', 2, '{few_responses}'::TEXT[]),
+        (3, 'C00', 1, '{low_response_rate}'::TEXT[])
+) AS sections (sid, section_name, instructor_id, quality_flags),
+(SELECT '{4,0,3,4,2,7}'::INTEGER[] AS scale) AS answers;
+
+INSERT INTO evaluations_quarantine (
+    sid, section_name, course_code, term_id, instructor_id, enrollment, responses,
+    class_helped_understanding, assignments_helped_understanding, fair_exams,
+    timely_feedback, developed_understanding, engaging, communication, help_opportunities,
+    effective_methods, timeliness, welcoming, materials, hours, expected_grades,
+    actual_grades, quality_flags, problems
+)
+SELECT
+    sid + offset_by, section_name, course_code, term_id, instructor_id, enrollment, responses,
+    class_helped_understanding, assignments_helped_understanding, fair_exams,
+    timely_feedback, developed_understanding, engaging, communication, help_opportunities,
+    effective_methods, timeliness, welcoming, materials, hours, expected_grades,
+    actual_grades, quality_flags, 'more responses than enrolled students'
+FROM evaluations, (VALUES (0), (1)) AS copies (offset_by)
+WHERE sid = 3;
@@ -0,0 +1,37 @@
+use crate::auth::AuthProvider;
+use crate::cookies::{import, Cookie};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use tokio::process::Command;
+
+/// Runs a shell command that prints cookies in any format `reauth --from-file` accepts.
+pub struct CommandProvider {
+    command: String,
+}
+
+impl CommandProvider {
+    pub fn new(command: &str) -> CommandProvider {
+        CommandProvider {
+            command: command.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for CommandProvider {
+    async fn fetch(&self) -> Result<Vec<Cookie>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!(
+                "Auth command exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        import::parse_export(&String::from_utf8(output.stdout)?)
+    }
+}
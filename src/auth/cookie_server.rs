@@ -0,0 +1,51 @@
+use crate::auth::AuthProvider;
+use crate::cookies::Cookie;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Asks the private cookie server, which logs in through Duo, for a new session.
+pub struct CookieServerProvider {
+    url: String,
+    token: String,
+}
+
+impl CookieServerProvider {
+    pub fn new(service_url: &str, token: &str) -> CookieServerProvider {
+        CookieServerProvider {
+            url: format!("{service_url}/cookies"),
+            token: token.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CookiesResponse {
+    pub name: String,
+    pub value: String,
+    /// Seconds since the epoch, or -1 for session cookies
+    #[serde(default, alias = "expiry")]
+    pub expires: Option<f64>,
+}
+
+#[async_trait]
+impl AuthProvider for CookieServerProvider {
+    async fn fetch(&self) -> Result<Vec<Cookie>> {
+        let client = reqwest::Client::new();
+        let res = client.post(&self.url).header("Authorization", &self.token);
+        let res = res.send().await?.error_for_status()?;
+        let json = res.json::<Vec<CookiesResponse>>().await?;
+
+        Ok(json
+            .into_iter()
+            .map(|cookie| Cookie {
+                name: cookie.name,
+                value: cookie.value,
+                expires: cookie
+                    .expires
+                    .filter(|expires| *expires > 0.0)
+                    .map(|expires| expires as i64),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,24 @@
+use crate::auth::AuthProvider;
+use crate::cookies::{import, Cookie};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+/// Reads a browser cookie export, so a session can be refreshed by re-exporting by hand.
+pub struct FileProvider {
+    path: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(path: impl Into<PathBuf>) -> FileProvider {
+        FileProvider { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for FileProvider {
+    async fn fetch(&self) -> Result<Vec<Cookie>> {
+        let contents = tokio::fs::read_to_string(&self.path).await?;
+        import::parse_export(&contents)
+    }
+}
@@ -0,0 +1,133 @@
+use crate::auth::cookie_server::CookiesResponse;
+use crate::cookies::import;
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long the made-up sessions handed out by the mock last.
+const SESSION_SECS: u64 = 30 * 60;
+
+struct MockState {
+    token: String,
+    cookies: Option<Vec<CookiesResponse>>,
+    issued: AtomicU64,
+}
+
+/// A stand-in for the cookie server. It checks the token like the real one and returns
+/// the cookies from `cookies` if given, or a new fake session for every request.
+pub fn app(token: &str, cookies: Option<&Path>) -> Result<Router> {
+    let cookies = match cookies {
+        Some(path) => Some(
+            import::parse_export(&std::fs::read_to_string(path)?)?
+                .into_iter()
+                .map(|cookie| CookiesResponse {
+                    name: cookie.name,
+                    value: cookie.value,
+                    expires: cookie.expires.map(|expires| expires as f64),
+                })
+                .collect(),
+        ),
+        None => None,
+    };
+
+    Ok(Router::new()
+        .route("/cookies", post(issue))
+        .with_state(Arc::new(MockState {
+            token: token.to_string(),
+            cookies,
+            issued: AtomicU64::new(0),
+        })))
+}
+
+async fn issue(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<CookiesResponse>>, StatusCode> {
+    let token = headers.get("Authorization").and_then(|h| h.to_str().ok());
+    if token != Some(state.token.as_str()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let n = state.issued.fetch_add(1, Ordering::Relaxed);
+    if let Some(cookies) = &state.cookies {
+        return Ok(Json(cookies.clone()));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(Json(vec![CookiesResponse {
+        name: "ASP.NET_SessionId".to_string(),
+        value: format!("mock-session-{n}"),
+        expires: Some((now + SESSION_SECS) as f64),
+    }]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::renewal::{check, SessionHealth};
+    use crate::auth::{AuthProvider, CookieServerProvider};
+    use crate::cookies::{CookieStore, FileCookieStore};
+    use crate::courses::get_units;
+    use crate::sessions::{Session, SessionConfig};
+    use crate::settings;
+    use crate::testing::{unused_pool, COOKIES_TOKEN};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reauth_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cookies_file = dir.path().join("cookies.json");
+        let config = SessionConfig {
+            name: "reauth".to_string(),
+            cookie_store: Some("file".to_string()),
+            cookies_file: Some(cookies_file.to_string_lossy().to_string()),
+            auth_provider: Some("cookie-server".to_string()),
+            auth_file: None,
+            auth_command: None,
+            requests_per_minute: None,
+        };
+        let session = Session::open(&config, &unused_pool()).await.unwrap();
+        assert!(matches!(
+            check(&session).await.unwrap(),
+            SessionHealth::Expired
+        ));
+
+        session.renew().await.unwrap();
+        let cookies = session.cookies().get();
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].name, "ASP.NET_SessionId");
+        assert!(cookies[0].value.starts_with("mock-session-"));
+        let left = crate::auth::renewal::expires_in(session.cookies()).unwrap();
+        assert!(left > Duration::from_secs(25 * 60) && left <= Duration::from_secs(30 * 60));
+
+        // The SET site accepts the new session, and it's saved for the next run
+        assert!(!get_units(session.upstream()).await.unwrap().is_empty());
+        assert!(matches!(
+            check(&session).await.unwrap(),
+            SessionHealth::Alive { .. }
+        ));
+        let saved = FileCookieStore::new(&cookies_file).load().await.unwrap();
+        assert_eq!(saved[0].value, cookies[0].value);
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_token() {
+        let url = &settings().service_url;
+        assert!(CookieServerProvider::new(url, "wrong")
+            .fetch()
+            .await
+            .is_err());
+        assert!(CookieServerProvider::new(url, COOKIES_TOKEN)
+            .fetch()
+            .await
+            .is_ok());
+    }
+}
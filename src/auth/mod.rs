@@ -0,0 +1,50 @@
+mod command;
+mod cookie_server;
+mod file;
+pub mod mock;
+pub mod renewal;
+
+pub use command::CommandProvider;
+pub use cookie_server::CookieServerProvider;
+pub use file::FileProvider;
+
+use crate::cookies::Cookie;
+use crate::settings;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+
+/// Somewhere to get a fresh set of SET site cookies from when the session runs out.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    async fn fetch(&self) -> Result<Vec<Cookie>>;
+}
+
+/// The provider named by `auth_provider` in the settings, the cookie server by default.
+pub fn provider() -> Result<Box<dyn AuthProvider>> {
+    provider_from(
+        settings().auth_provider.as_deref(),
+        settings().auth_file.as_deref(),
+        settings().auth_command.as_deref(),
+    )
+}
+
+/// The provider named `kind`, configured with `file` or `command` where it needs them.
+pub fn provider_from(
+    kind: Option<&str>,
+    file: Option<&str>,
+    command: Option<&str>,
+) -> Result<Box<dyn AuthProvider>> {
+    Ok(match kind {
+        None | Some("cookie-server") => Box::new(CookieServerProvider::new(
+            &settings().service_url,
+            &settings().cookies_token,
+        )),
+        Some("file") => Box::new(FileProvider::new(
+            file.ok_or(anyhow!("auth_file must be set for the file auth provider"))?,
+        )),
+        Some("command") => Box::new(CommandProvider::new(command.ok_or(anyhow!(
+            "auth_command must be set for the command auth provider"
+        ))?)),
+        Some(other) => bail!("Unknown auth provider {other:?}"),
+    })
+}
@@ -0,0 +1,100 @@
+use crate::cookies::SessionCookies;
+use crate::courses::{get_units_response, parse_units};
+use crate::evaluations::is_login_page;
+use crate::sessions::Session;
+use crate::upstream::{MissingRecording, StatusError};
+use anyhow::{bail, Context, Result};
+use scraper::Html;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How often long runs check on the session.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Cookies are renewed once they are this close to expiring.
+const RENEW_MARGIN: Duration = Duration::from_secs(10 * 60);
+
+pub enum SessionHealth {
+    Alive { expires_in: Option<Duration> },
+    Expired,
+}
+
+/// Makes a cheap authenticated request to see whether the SET site still accepts the session.
+/// Only a login page or an auth failure counts as expired; anything else that goes wrong is
+/// returned, since renewing wouldn't fix it. When replaying, a cassette without the request
+/// counts as expired too.
+pub async fn check(session: &Session) -> Result<SessionHealth> {
+    let res = match get_units_response(session.checks()).await {
+        Ok(res) => res,
+        Err(e) if is_expired(&e) => return Ok(SessionHealth::Expired),
+        Err(e) => return Err(e),
+    };
+    if is_login_page(&Html::parse_document(&res)) {
+        return Ok(SessionHealth::Expired);
+    }
+    let units = parse_units(&res).context("Unexpected response to the units request")?;
+    if units.is_empty() {
+        bail!("SET site returned no units");
+    }
+    Ok(SessionHealth::Alive {
+        expires_in: expires_in(session.cookies()),
+    })
+}
+
+fn is_expired(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<StatusError>()
+        .is_some_and(StatusError::is_auth)
+        || e.downcast_ref::<MissingRecording>().is_some()
+}
+
+/// Time left until the first session cookie expires, going by the cookies' own attributes.
+pub fn expires_in(cookies: &SessionCookies) -> Option<Duration> {
+    cookies.expires_at().map(|expires| {
+        expires
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    })
+}
+
+/// Renews the session in the background before it lapses. The task stops when the guard
+/// is dropped. Nothing is renewed while replaying a cassette.
+pub fn spawn(session: Arc<Session>) -> RenewalGuard {
+    if session.upstream().is_replaying() {
+        return RenewalGuard(None);
+    }
+    RenewalGuard(Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = renew_if_needed(&session).await {
+                warn!(session = session.name, error = %e, "could not renew session");
+            }
+        }
+    })))
+}
+
+async fn renew_if_needed(session: &Session) -> Result<()> {
+    let expiring = expires_in(session.cookies()).is_some_and(|left| left < RENEW_MARGIN);
+    let expired = !expiring && matches!(check(session).await?, SessionHealth::Expired);
+    if expiring || expired {
+        info!(
+            session = session.name,
+            reason = if expired { "expired" } else { "expiring soon" },
+            "renewing session"
+        );
+        session.renew().await?;
+    }
+    Ok(())
+}
+
+pub struct RenewalGuard(Option<JoinHandle<()>>);
+
+impl Drop for RenewalGuard {
+    fn drop(&mut self) {
+        if let Some(task) = &self.0 {
+            task.abort();
+        }
+    }
+}
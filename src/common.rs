@@ -1,9 +1,12 @@
-use crate::cookies::get_cookies;
-use crate::settings;
+use crate::cookies::SessionCookies;
+use crate::logging;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 use reqwest::header::HeaderValue;
 use reqwest::{Client, Proxy};
-use std::fmt::Write;
+use std::fmt::{Debug, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_retry::strategy::{jitter, ExponentialBackoff};
 
 pub fn progress_bar(max: u64) -> ProgressBar {
     let pb = ProgressBar::new(max);
@@ -17,26 +20,50 @@ pub fn progress_bar(max: u64) -> ProgressBar {
         })
         .progress_chars("#>-"),
     );
+    logging::set_progress_bar(&pb);
     pb
 }
 
-pub fn client() -> reqwest::Result<Client> {
+/// How many rows a dry run prints of each kind.
+const DRY_RUN_SAMPLE: usize = 5;
+
+/// Describes what a dry run would have written: how many rows, how many of them are new
+/// and the first few.
+pub fn dry_run_summary<T: Debug>(kind: &str, rows: &[T], existing: i64) -> String {
+    let mut summary = format!(
+        "Would save {} {kind} ({} new, {existing} already stored)",
+        rows.len(),
+        rows.len() as i64 - existing
+    );
+    for row in rows.iter().take(DRY_RUN_SAMPLE) {
+        write!(summary, "\n  {row:?}").unwrap();
+    }
+    summary
+}
+
+/// Backoff used when retrying upstream requests: roughly 1s, 2s, 4s, 8s.
+pub fn retry_strategy() -> impl Iterator<Item = Duration> {
+    ExponentialBackoff::from_millis(2)
+        .factor(500)
+        .max_delay(Duration::from_secs(10))
+        .map(jitter)
+        .take(4)
+}
+
+/// Builds a client for the SET site that sends `cookies`, optionally through `proxy`.
+pub fn client(cookies: Arc<SessionCookies>, proxy: Option<Proxy>) -> reqwest::Result<Client> {
     let mut headers = reqwest::header::HeaderMap::new();
     headers.insert(
         "User-Agent",
         HeaderValue::from_static("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/123.0.0.0 Safari/537.3"),
     );
-    headers.insert("Cookie", HeaderValue::from_str(&get_cookies()).unwrap());
 
     let mut builder = Client::builder()
         // .timeout(std::time::Duration::from_secs(3))
-        .default_headers(headers);
-    if let (Some(username), Some(password)) =
-        (&settings().proxy_username, &settings().proxy_password)
-    {
-        builder = builder.proxy(
-            Proxy::all(format!("{}:5000", settings().service_url))?.basic_auth(username, password),
-        );
+        .default_headers(headers)
+        .cookie_provider(cookies);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
     }
     builder.build()
 }
@@ -0,0 +1,42 @@
+use crate::cookies::{from_header, Cookie, CookieStore};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::fs;
+
+pub const DEFAULT_FILENAME: &str = "cookies.txt";
+
+/// Keeps cookies in a JSON file. Plain `a=1;b=2;` files written by older versions are
+/// still read.
+pub struct FileCookieStore {
+    path: PathBuf,
+}
+
+impl FileCookieStore {
+    pub fn new(path: impl Into<PathBuf>) -> FileCookieStore {
+        FileCookieStore { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl CookieStore for FileCookieStore {
+    async fn load(&self) -> Result<Vec<Cookie>> {
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        if contents.trim_start().starts_with('[') {
+            Ok(serde_json::from_str(&contents)?)
+        } else {
+            Ok(from_header(&contents))
+        }
+    }
+
+    async fn save(&self, cookies: &[Cookie]) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(cookies)?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::cookies::{from_header, Cookie};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// The host the scraper talks to. Imported cookies for any other site are dropped.
+const HOST: &str = "academicaffairs.ucsd.edu";
+
+/// Parses cookies exported from a browser, keeping only the ones sent to the SET site.
+/// Accepts a Netscape `cookies.txt`, a JSON export from extensions such as Cookie-Editor or
+/// EditThisCookie, or a raw `Cookie:` header copied from the network tab.
+pub fn parse_export(contents: &str) -> Result<Vec<Cookie>> {
+    let trimmed = contents.trim();
+    let cookies = if trimmed.starts_with('[') || trimmed.starts_with('{') {
+        parse_json(trimmed)?
+    } else if trimmed.lines().any(|line| line.split('\t').count() == 7) {
+        parse_netscape(trimmed)?
+    } else {
+        let header = trimmed
+            .strip_prefix("Cookie:")
+            .or_else(|| trimmed.strip_prefix("cookie:"))
+            .unwrap_or(trimmed);
+        from_header(header)
+            .into_iter()
+            .map(|cookie| (None, cookie))
+            .collect()
+    };
+
+    Ok(cookies
+        .into_iter()
+        .filter(|(domain, _)| domain.as_deref().is_none_or(matches_host))
+        .map(|(_, cookie)| cookie)
+        .collect())
+}
+
+/// Whether a cookie set for `domain` is sent to the SET site.
+fn matches_host(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+    HOST == domain || HOST.ends_with(&format!(".{domain}"))
+}
+
+#[derive(Deserialize)]
+struct JsonCookie {
+    #[serde(alias = "Name")]
+    name: String,
+    #[serde(alias = "Value")]
+    value: String,
+    #[serde(default, alias = "host", alias = "Domain")]
+    domain: Option<String>,
+    #[serde(default, alias = "expires", alias = "expiry")]
+    #[serde(rename = "expirationDate")]
+    expiration_date: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonExport {
+    List(Vec<JsonCookie>),
+    Wrapped { cookies: Vec<JsonCookie> },
+}
+
+fn parse_json(contents: &str) -> Result<Vec<(Option<String>, Cookie)>> {
+    let cookies = match serde_json::from_str::<JsonExport>(contents)? {
+        JsonExport::List(cookies) | JsonExport::Wrapped { cookies } => cookies,
+    };
+
+    Ok(cookies
+        .into_iter()
+        .map(|cookie| {
+            (
+                cookie.domain,
+                Cookie {
+                    name: cookie.name,
+                    value: cookie.value,
+                    expires: cookie
+                        .expiration_date
+                        .filter(|expires| *expires > 0.0)
+                        .map(|expires| expires as i64),
+                },
+            )
+        })
+        .collect())
+}
+
+fn parse_netscape(contents: &str) -> Result<Vec<(Option<String>, Cookie)>> {
+    contents
+        .lines()
+        .map(|line| line.strip_prefix("#HttpOnly_").unwrap_or(line))
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let [domain, _, _, _, expires, name, value] = fields[..] else {
+                return Err(anyhow!("Malformed cookies.txt line: {line:?}"));
+            };
+            Ok((
+                Some(domain.to_string()),
+                Cookie {
+                    name: name.to_string(),
+                    value: value.trim_end().to_string(),
+                    expires: expires.parse::<i64>().ok().filter(|expires| *expires > 0),
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookie(name: &str, value: &str, expires: Option<i64>) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires,
+        }
+    }
+
+    #[test]
+    fn netscape_export() {
+        let export = "\
+# Netscape HTTP Cookie File
+# https://curl.se/docs/http-cookies.html
+
+#HttpOnly_academicaffairs.ucsd.edu\tFALSE\t/\tTRUE\t0\tASP.NET_SessionId\tabc123
+.ucsd.edu\tTRUE\t/\tTRUE\t1893456000\tshib\txyz\r
+.google.com\tTRUE\t/\tTRUE\t1893456000\tNID\tnope
+";
+        assert_eq!(
+            parse_export(export).unwrap(),
+            [
+                cookie("ASP.NET_SessionId", "abc123", None),
+                cookie("shib", "xyz", Some(1893456000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_exports() {
+        let cookie_editor = r#"[
+            {"name": "ASP.NET_SessionId", "value": "abc123", "domain": "academicaffairs.ucsd.edu", "expirationDate": 1893456000.5, "hostOnly": true},
+            {"name": "session", "value": "other", "domain": ".example.com"},
+            {"name": "shib", "value": "xyz"}
+        ]"#;
+        assert_eq!(
+            parse_export(cookie_editor).unwrap(),
+            [
+                cookie("ASP.NET_SessionId", "abc123", Some(1893456000)),
+                cookie("shib", "xyz", None),
+            ]
+        );
+
+        let wrapped = r#"{"cookies": [{"Name": "shib", "Value": "xyz", "Domain": ".ucsd.edu", "expiry": -1}]}"#;
+        assert_eq!(
+            parse_export(wrapped).unwrap(),
+            [cookie("shib", "xyz", None)]
+        );
+    }
+
+    #[test]
+    fn cookie_header() {
+        assert_eq!(
+            parse_export("Cookie: ASP.NET_SessionId=abc123; shib=xyz\n").unwrap(),
+            [
+                cookie("ASP.NET_SessionId", "abc123", None),
+                cookie("shib", "xyz", None),
+            ]
+        );
+        assert_eq!(
+            parse_export("shib=xyz").unwrap(),
+            [cookie("shib", "xyz", None)]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_exports() {
+        let truncated = "\
+academicaffairs.ucsd.edu\tFALSE\t/\tTRUE\t0\tASP.NET_SessionId\tabc123
+academicaffairs.ucsd.edu\tFALSE\t/\tTRUE
+";
+        assert!(parse_export(truncated).is_err());
+        assert!(parse_export(r#"[{"name": "shib"}]"#).is_err());
+        assert!(parse_export("{not json").is_err());
+    }
+}
@@ -0,0 +1,22 @@
+use crate::cookies::{Cookie, CookieStore};
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+
+/// Keeps cookies for the life of the process only.
+#[derive(Default)]
+pub struct MemoryCookieStore {
+    cookies: RwLock<Vec<Cookie>>,
+}
+
+#[async_trait]
+impl CookieStore for MemoryCookieStore {
+    async fn load(&self) -> Result<Vec<Cookie>> {
+        Ok(self.cookies.read().await.clone())
+    }
+
+    async fn save(&self, cookies: &[Cookie]) -> Result<()> {
+        *self.cookies.write().await = cookies.to_vec();
+        Ok(())
+    }
+}
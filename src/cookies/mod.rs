@@ -0,0 +1,220 @@
+mod file;
+pub mod import;
+mod memory;
+mod postgres;
+
+pub use file::FileCookieStore;
+pub use memory::MemoryCookieStore;
+pub use postgres::PostgresCookieStore;
+
+use crate::sessions::DEFAULT_NAME;
+use crate::settings;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+
+static STORE: OnceCell<Arc<dyn CookieStore>> = OnceCell::const_new();
+static SESSION: OnceCell<Arc<SessionCookies>> = OnceCell::const_new();
+
+/// The configured cookie store, for holding on to alongside other sessions' stores.
+pub fn shared_store() -> Arc<dyn CookieStore> {
+    STORE.get().unwrap().clone()
+}
+
+/// The cookies every scraper client sends.
+pub fn session() -> Arc<SessionCookies> {
+    SESSION.get().unwrap().clone()
+}
+
+/// Picks the cookie store from the settings and loads the saved cookies into the session.
+pub async fn init(conn: &Pool<Postgres>) -> Result<()> {
+    let store = open_store(
+        settings().cookie_store.as_deref(),
+        Some(
+            settings()
+                .cookies_file
+                .as_deref()
+                .unwrap_or(file::DEFAULT_FILENAME),
+        ),
+        conn,
+        DEFAULT_NAME,
+    )?;
+    let session = SessionCookies::new(store.load().await?);
+
+    if STORE.set(store).is_err() || SESSION.set(Arc::new(session)).is_err() {
+        bail!("Cookies were already initialized");
+    }
+
+    Ok(())
+}
+
+/// The cookie store called `kind`, keeping cookies in `file` for the file store and under
+/// `session` in the `cookies` table for the Postgres one.
+pub fn open_store(
+    kind: Option<&str>,
+    file: Option<&str>,
+    conn: &Pool<Postgres>,
+    session: &str,
+) -> Result<Arc<dyn CookieStore>> {
+    Ok(match kind {
+        None | Some("file") => Arc::new(FileCookieStore::new(file.ok_or(anyhow!(
+            "cookies_file must be set for the file cookie store"
+        ))?)),
+        Some("postgres") => Arc::new(PostgresCookieStore::new(conn.clone(), session)),
+        Some("memory") => Arc::new(MemoryCookieStore::default()),
+        Some(other) => bail!("Unknown cookie store {other:?}"),
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Unix timestamp after which the cookie is no longer sent, if known
+    #[serde(default)]
+    pub expires: Option<i64>,
+}
+
+impl Cookie {
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= unix_now())
+    }
+}
+
+/// Somewhere to keep cookies between runs.
+#[async_trait]
+pub trait CookieStore: Send + Sync {
+    /// Returns the saved cookies, or nothing if none have been saved yet.
+    async fn load(&self) -> Result<Vec<Cookie>>;
+    async fn save(&self, cookies: &[Cookie]) -> Result<()>;
+}
+
+/// Cookies shared by every client built with them. Replacing them changes what live clients
+/// send without rebuilding the clients, and `Set-Cookie` responses update them in place.
+#[derive(Default)]
+pub struct SessionCookies {
+    cookies: RwLock<Vec<Cookie>>,
+    dirty: AtomicBool,
+}
+
+impl SessionCookies {
+    pub fn new(cookies: Vec<Cookie>) -> SessionCookies {
+        SessionCookies {
+            cookies: RwLock::new(cookies),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self) -> Vec<Cookie> {
+        self.cookies.read().unwrap().clone()
+    }
+
+    /// Whether `Set-Cookie` responses have changed the cookies since this was last asked.
+    pub fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn replace(&self, cookies: Vec<Cookie>) {
+        *self.cookies.write().unwrap() = cookies;
+        self.dirty.store(false, Ordering::Relaxed);
+    }
+
+    /// Applies a `Set-Cookie` header, removing the cookie if the server cleared it.
+    fn update(&self, header: &str) {
+        let Ok(parsed) = cookie::Cookie::parse(header) else {
+            return;
+        };
+        let cookie = Cookie {
+            name: parsed.name().to_string(),
+            value: parsed.value().to_string(),
+            expires: parsed
+                .max_age()
+                .map(|age| unix_now() + age.whole_seconds())
+                .or_else(|| parsed.expires_datetime().map(|t| t.unix_timestamp())),
+        };
+
+        let mut cookies = self.cookies.write().unwrap();
+        let existing = cookies.iter().position(|c| c.name == cookie.name);
+        if existing.is_some_and(|i| cookies[i] == cookie) {
+            return;
+        }
+        if let Some(i) = existing {
+            cookies.remove(i);
+        }
+        if !cookie.value.is_empty() && !cookie.is_expired() {
+            cookies.push(cookie);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// When the first of the cookies expires, if any of them carry an expiry.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.cookies
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|cookie| cookie.expires)
+            .min()
+            .map(|expires| UNIX_EPOCH + Duration::from_secs(expires.max(0) as u64))
+    }
+}
+
+impl reqwest::cookie::CookieStore for SessionCookies {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, _url: &Url) {
+        for header in cookie_headers {
+            if let Ok(header) = header.to_str() {
+                self.update(header);
+            }
+        }
+    }
+
+    fn cookies(&self, _url: &Url) -> Option<HeaderValue> {
+        let header = to_header(
+            self.cookies
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|cookie| !cookie.is_expired()),
+        );
+        if header.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+/// Formats cookies as a `Cookie` header value.
+pub fn to_header<'a>(cookies: impl IntoIterator<Item = &'a Cookie>) -> String {
+    cookies
+        .into_iter()
+        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Parses a `Cookie` header value such as `a=1; b=2`.
+pub fn from_header(header: &str) -> Vec<Cookie> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, value)| Cookie {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+            expires: None,
+        })
+        .collect()
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
@@ -0,0 +1,83 @@
+use crate::cookies::{Cookie, CookieStore};
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{query, query_as, Pool, Postgres};
+
+/// Keeps cookies in the `cookies` table so every machine pointed at the database shares them.
+/// Each session's cookies are kept apart by its name.
+pub struct PostgresCookieStore {
+    conn: Pool<Postgres>,
+    session: String,
+}
+
+impl PostgresCookieStore {
+    pub fn new(conn: Pool<Postgres>, session: &str) -> PostgresCookieStore {
+        PostgresCookieStore {
+            conn,
+            session: session.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CookieStore for PostgresCookieStore {
+    async fn load(&self) -> Result<Vec<Cookie>> {
+        Ok(query_as!(
+            Cookie,
+            "SELECT name, value, expires FROM cookies WHERE session = $1 ORDER BY name",
+            self.session
+        )
+        .fetch_all(&self.conn)
+        .await?)
+    }
+
+    async fn save(&self, cookies: &[Cookie]) -> Result<()> {
+        let mut tx = self.conn.begin().await?;
+
+        query!("DELETE FROM cookies WHERE session = $1", self.session)
+            .execute(&mut *tx)
+            .await?;
+        query!(
+            "
+                INSERT INTO cookies (session, name, value, expires)
+                SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::bigint[])
+            ",
+            self.session,
+            &cookies.iter().map(|c| c.name.clone()).collect::<Vec<_>>()[..],
+            &cookies.iter().map(|c| c.value.clone()).collect::<Vec<_>>()[..],
+            &cookies.iter().map(|c| c.expires).collect::<Vec<_>>()[..] as &[Option<i64>]
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookie(value: &str) -> Cookie {
+        Cookie {
+            name: "ASP.NET_SessionId".to_string(),
+            value: value.to_string(),
+            expires: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn sessions_keep_their_own_cookies(conn: Pool<Postgres>) -> Result<()> {
+        let first = PostgresCookieStore::new(conn.clone(), "first");
+        let second = PostgresCookieStore::new(conn.clone(), "second");
+        first.save(&[cookie("a")]).await?;
+        second.save(&[cookie("b")]).await?;
+        first.save(&[cookie("c")]).await?;
+
+        assert_eq!(first.load().await?, [cookie("c")]);
+        assert_eq!(second.load().await?, [cookie("b")]);
+        Ok(())
+    }
+}
@@ -1,12 +1,18 @@
 use crate::common;
 use crate::database::{Course, Unit};
-use anyhow::Result;
+use crate::sessions::Session;
+use crate::upstream::Upstream;
+use crate::windows;
+use anyhow::{bail, Result};
 use futures::{stream, StreamExt};
-use reqwest::Client;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use sqlx::{query, Pool, Postgres};
 use std::collections::HashMap;
+use std::sync::LazyLock;
 use tokio::time::Instant;
+use tokio_retry::Retry;
+use tracing::{info, info_span, instrument, warn, Instrument};
 
 #[derive(Deserialize)]
 struct ResponseItem {
@@ -19,6 +25,74 @@ struct ResponseList {
     d: Vec<ResponseItem>,
 }
 
+/// A course code split into its parts, e.g. "CSE 120A" is `CSE`, `120` and `A`.
+#[derive(Debug, PartialEq)]
+pub struct CourseCode {
+    pub subject: String,
+    pub number: i32,
+    pub suffix: Option<String>,
+}
+
+/// "SUBJ 123X" at the start of a course code or name: letters, up to nine digits and an
+/// optional suffix starting with a letter. The course_codes migration backfills with the
+/// same pattern.
+static COURSE_CODE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([A-Za-z]+)\s+([0-9]{1,9})([A-Za-z][A-Za-z0-9]*)?(\s|$)").unwrap()
+});
+
+impl CourseCode {
+    /// Parses the leading "SUBJ 123X" of a course code or name, ignoring anything after it.
+    pub fn parse(s: &str) -> Option<CourseCode> {
+        let m = COURSE_CODE.captures(s)?;
+        Some(CourseCode {
+            subject: m[1].to_string(),
+            number: m[2].parse().ok()?,
+            suffix: m.get(3).map(|suffix| suffix.as_str().to_string()),
+        })
+    }
+
+    pub fn level(&self) -> Option<CourseLevel> {
+        CourseLevel::from_number(self.number)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CourseLevel {
+    Lower,
+    Upper,
+    Graduate,
+}
+
+impl CourseLevel {
+    /// Classifies a course by number alone. Suffixes mark versions of a course numbered
+    /// in the same band as it, so they never change the level: "L" labs, "A"-"D"
+    /// sequences, "H" honors ("199H"), "R" remote and "S"/"GS" summer and Global Seminar
+    /// offerings. Numbers past the graduate bands aren't UCSD course numbers and get no
+    /// level.
+    pub fn from_number(number: i32) -> Option<CourseLevel> {
+        match number {
+            // Includes freshman seminars (87), seminars (90) and independent study (99)
+            ..=99 => Some(CourseLevel::Lower),
+            // Includes senior seminars (192), apprentice teaching (195), internships (197)
+            // and directed and independent study (198, 199)
+            100..=199 => Some(CourseLevel::Upper),
+            // Graduate courses and seminars, 300-level teacher preparation, 400-level
+            // professional school courses and 500-level graduate apprentice teaching
+            200..=599 => Some(CourseLevel::Graduate),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CourseLevel::Lower => "lower",
+            CourseLevel::Upper => "upper",
+            CourseLevel::Graduate => "graduate",
+        }
+    }
+}
+
 pub async fn display_stats(conn: &Pool<Postgres>) -> Result<()> {
     let unit_count = query!("SELECT COUNT(*) FROM units")
         .fetch_one(conn)
@@ -38,40 +112,105 @@ pub async fn display_stats(conn: &Pool<Postgres>) -> Result<()> {
     Ok(())
 }
 
-pub async fn get_all_courses(conn: &Pool<Postgres>) -> Result<()> {
-    let client = common::client()?;
+#[instrument(name = "run", skip_all, fields(stage = "courses", dry_run))]
+pub async fn get_all_courses(
+    conn: &Pool<Postgres>,
+    session: &Session,
+    allow_partial: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let upstream = session.upstream();
 
-    let units = get_units(&client).await?;
-    println!("Found {:?} units", units.len());
+    windows::wait_for_open().await;
+    let units = get_units(upstream).await?;
+    info!(units = units.len(), "found units");
 
     let pb = common::progress_bar(units.len() as u64);
-    let mut courses = stream::iter(&units)
-        .map(|unit| {
-            let client = &client;
+    // Indices rather than references keep the futures `Send` for `serve`'s scheduler
+    let results = stream::iter(0..units.len())
+        .map(|i| {
+            let unit = &units[i];
             let pb = &pb;
             async move {
+                windows::wait_for_open().await;
                 let start = Instant::now();
-                let res = get_courses(client, unit.id).await;
-                pb.println(format!(
-                    "[+] found courses for {} in {:?}",
-                    unit.name,
-                    start.elapsed()
-                ));
+                let res = Retry::spawn(common::retry_strategy(), || async {
+                    get_courses(upstream, unit.id)
+                        .await
+                        .inspect_err(|e| warn!(error = %e, "failed to fetch courses, retrying"))
+                })
+                .await;
+                if let Ok(found) = &res {
+                    info!(
+                        elapsed_ms = start.elapsed().as_millis() as u64,
+                        courses = found.len(),
+                        "found courses"
+                    );
+                }
                 pb.inc(1);
-                res
+                (unit, res)
             }
+            .instrument(info_span!("unit", id = unit.id, name = %unit.name))
         })
         .buffer_unordered(4)
-        .filter_map(|r| async { r.ok() })
         .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>();
+        .await;
+
+    let mut courses = Vec::new();
+    let mut failures = Vec::new();
+    for (unit, res) in results {
+        match res {
+            Ok(found) => courses.extend(found),
+            Err(e) => failures.push((unit, e)),
+        }
+    }
 
     pb.finish();
-    println!("Found {:?} courses", courses.len());
+    if !dry_run {
+        session.save().await?;
+    }
+    info!(courses = courses.len(), "found courses");
+
+    courses.sort_unstable_by(|a, b| a.code.cmp(&b.code));
+    courses.dedup_by(|a, b| a.code == b.code);
+
+    let codes = courses
+        .iter()
+        .map(|c| CourseCode::parse(&c.name).or_else(|| CourseCode::parse(&c.code)))
+        .collect::<Vec<_>>();
+    let unparsed = codes.iter().filter(|c| c.is_none()).count();
+    if unparsed > 0 {
+        warn!(courses = unparsed, "could not parse course codes");
+    }
 
+    if dry_run {
+        preview_courses(conn, &units, &courses, &codes).await?;
+    } else {
+        save_courses(conn, &units, courses, &codes).await?;
+    }
+
+    if !failures.is_empty() {
+        for (unit, e) in &failures {
+            warn!(unit = unit.id, name = %unit.name, error = %e, "missing courses for unit");
+        }
+        if !allow_partial {
+            bail!("Failed to fetch courses for {} units", failures.len());
+        }
+        warn!(
+            units = failures.len(),
+            "partial run, units that failed keep their old courses"
+        );
+    }
+
+    Ok(())
+}
+
+async fn save_courses(
+    conn: &Pool<Postgres>,
+    units: &[Unit],
+    courses: Vec<Course>,
+    codes: &[Option<CourseCode>],
+) -> Result<()> {
     let saved = query!(
         "
             INSERT INTO units (id, name)
@@ -80,52 +219,103 @@ pub async fn get_all_courses(conn: &Pool<Postgres>) -> Result<()> {
             SET name = EXCLUDED.name
         ",
         &units.iter().map(|u| u.id).collect::<Vec<_>>()[..],
-        &units.into_iter().map(|u| u.name).collect::<Vec<_>>()[..]
+        &units.iter().map(|u| u.name.clone()).collect::<Vec<_>>()[..]
     )
     .execute(conn)
     .await?
     .rows_affected();
 
-    println!("Saved {} units", saved);
-
-    courses.sort_unstable_by(|a, b| a.code.cmp(&b.code));
-    courses.dedup_by(|a, b| a.code == b.code);
+    info!(saved, "saved units");
 
     let saved = query!(
         "
-            INSERT INTO courses (code, unit_id, name)
-            SELECT * FROM UNNEST($1::text[], $2::int[], $3::text[])
+            INSERT INTO courses (code, unit_id, name, subject, number, suffix, level)
+            SELECT * FROM UNNEST($1::text[], $2::int[], $3::text[], $4::text[], $5::int[], $6::text[], $7::text[])
             ON CONFLICT (code) DO UPDATE
-            SET name = EXCLUDED.name
+            SET name = EXCLUDED.name,
+                subject = EXCLUDED.subject,
+                number = EXCLUDED.number,
+                suffix = EXCLUDED.suffix,
+                level = EXCLUDED.level
         ",
         &courses.iter().map(|c| c.code.clone()).collect::<Vec<_>>()[..],
         &courses.iter().map(|c| c.unit_id).collect::<Vec<_>>()[..],
-        &courses.into_iter().map(|c| c.name).collect::<Vec<_>>()[..]
+        &courses.into_iter().map(|c| c.name).collect::<Vec<_>>()[..],
+        &codes
+            .iter()
+            .map(|c| c.as_ref().map(|c| c.subject.clone()))
+            .collect::<Vec<_>>()[..] as &[Option<String>],
+        &codes
+            .iter()
+            .map(|c| c.as_ref().map(|c| c.number))
+            .collect::<Vec<_>>()[..] as &[Option<i32>],
+        &codes
+            .iter()
+            .map(|c| c.as_ref().and_then(|c| c.suffix.clone()))
+            .collect::<Vec<_>>()[..] as &[Option<String>],
+        &codes
+            .iter()
+            .map(|c| c.as_ref().and_then(|c| c.level()).map(|level| level.as_str().to_string()))
+            .collect::<Vec<_>>()[..] as &[Option<String>]
     )
     .execute(conn)
     .await?
     .rows_affected();
 
-    println!("Saved {} courses", saved);
+    info!(saved, "saved courses");
+
+    Ok(())
+}
+
+/// Prints what `save_courses` would write without writing it.
+async fn preview_courses(
+    conn: &Pool<Postgres>,
+    units: &[Unit],
+    courses: &[Course],
+    codes: &[Option<CourseCode>],
+) -> Result<()> {
+    let existing = query!(
+        "SELECT COUNT(*) FROM units WHERE id = ANY($1)",
+        &units.iter().map(|u| u.id).collect::<Vec<_>>()[..]
+    )
+    .fetch_one(conn)
+    .await?
+    .count
+    .unwrap_or(0);
+    println!("{}", common::dry_run_summary("units", units, existing));
+
+    let existing = query!(
+        "SELECT COUNT(*) FROM courses WHERE code = ANY($1)",
+        &courses.iter().map(|c| c.code.clone()).collect::<Vec<_>>()[..]
+    )
+    .fetch_one(conn)
+    .await?
+    .count
+    .unwrap_or(0);
+    let rows = courses.iter().zip(codes).collect::<Vec<_>>();
+    println!("{}", common::dry_run_summary("courses", &rows, existing));
 
     Ok(())
 }
 
-async fn get_units(client: &Client) -> Result<Vec<Unit>> {
+pub async fn get_units(upstream: &Upstream) -> Result<Vec<Unit>> {
+    parse_units(&get_units_response(upstream).await?)
+}
+
+/// The SET site's raw answer to the units request, which is also how sessions are checked.
+pub async fn get_units_response(upstream: &Upstream) -> Result<String> {
     let mut body = HashMap::new();
     body.insert("knownCategoryValues", "");
     body.insert("category", "Unit");
     body.insert("contextKey", "UnitID:0");
 
-    let res = client
-        .post("https://academicaffairs.ucsd.edu/Modules/Evals/SET/Reports/Search.aspx/GetUnits")
-        .json(&body)
-        .send()
-        .await?;
+    upstream
+        .post_json("/Modules/Evals/SET/Reports/Search.aspx/GetUnits", &body)
+        .await
+}
 
-    Ok(res
-        .json::<ResponseList>()
-        .await?
+pub fn parse_units(res: &str) -> Result<Vec<Unit>> {
+    Ok(serde_json::from_str::<ResponseList>(res)?
         .d
         .iter()
         .filter_map(|item| {
@@ -137,20 +327,16 @@ async fn get_units(client: &Client) -> Result<Vec<Unit>> {
         .collect::<Vec<Unit>>())
 }
 
-async fn get_courses(client: &Client, unit_id: i32) -> Result<Vec<Course>> {
+async fn get_courses(upstream: &Upstream, unit_id: i32) -> Result<Vec<Course>> {
     let mut body = HashMap::new();
     body.insert("knownCategoryValues", format!("Unit:{}", unit_id));
     body.insert("category", "Course".to_string());
     body.insert("contextKey", "SubjectCode:;CourseCode:".to_string());
 
-    let res = client
-        .post("https://academicaffairs.ucsd.edu/Modules/Evals/SET/Reports/Search.aspx/GetCourses")
-        .json(&body)
-        .send()
+    let res = upstream
+        .post_json("/Modules/Evals/SET/Reports/Search.aspx/GetCourses", &body)
         .await?;
-    let res = res
-        .json::<ResponseList>()
-        .await?
+    let res = serde_json::from_str::<ResponseList>(&res)?
         .d
         .into_iter()
         .map(|item| Course {
@@ -161,3 +347,86 @@ async fn get_courses(client: &Client, unit_id: i32) -> Result<Vec<Course>> {
         .collect::<Vec<Course>>();
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(subject: &str, number: i32, suffix: Option<&str>) -> Option<CourseCode> {
+        Some(CourseCode {
+            subject: subject.to_string(),
+            number,
+            suffix: suffix.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn parses_codes_and_names() {
+        assert_eq!(CourseCode::parse("CSE 120"), code("CSE", 120, None));
+        assert_eq!(CourseCode::parse("CSE 8A"), code("CSE", 8, Some("A")));
+        assert_eq!(CourseCode::parse("CSE 199H"), code("CSE", 199, Some("H")));
+        assert_eq!(
+            CourseCode::parse("HIST 117GS"),
+            code("HIST", 117, Some("GS"))
+        );
+        assert_eq!(CourseCode::parse("CHEM 7LM"), code("CHEM", 7, Some("LM")));
+        assert_eq!(
+            CourseCode::parse("CSE 12 - Basic Data Structures"),
+            code("CSE", 12, None)
+        );
+        assert_eq!(
+            CourseCode::parse("MAE  140\tLinear"),
+            code("MAE", 140, None)
+        );
+    }
+
+    #[test]
+    fn rejects_other_shapes() {
+        assert_eq!(CourseCode::parse("CSE"), None);
+        assert_eq!(CourseCode::parse("CSE A1"), None);
+        assert_eq!(CourseCode::parse("120 CSE"), None);
+        assert_eq!(CourseCode::parse("CSE 1-2"), None);
+        assert_eq!(CourseCode::parse("CSE 120/220"), None);
+        assert_eq!(CourseCode::parse(" CSE 120"), None);
+        assert_eq!(CourseCode::parse("CSE 1234567890"), None);
+        assert_eq!(CourseCode::parse("C1E 120"), None);
+    }
+
+    #[test]
+    fn levels_follow_number_bands() {
+        let level = |s: &str| CourseCode::parse(s).unwrap().level();
+        assert_eq!(level("MATH 2"), Some(CourseLevel::Lower));
+        assert_eq!(level("CSE 87"), Some(CourseLevel::Lower));
+        assert_eq!(level("CSE 90"), Some(CourseLevel::Lower));
+        assert_eq!(level("CSE 99H"), Some(CourseLevel::Lower));
+        assert_eq!(level("CHEM 7L"), Some(CourseLevel::Lower));
+        assert_eq!(level("ANTH 21GS"), Some(CourseLevel::Lower));
+        assert_eq!(level("CSE 100"), Some(CourseLevel::Upper));
+        assert_eq!(level("CSE 120A"), Some(CourseLevel::Upper));
+        assert_eq!(level("CSE 192"), Some(CourseLevel::Upper));
+        assert_eq!(level("CSE 199H"), Some(CourseLevel::Upper));
+        assert_eq!(level("MUS 195S"), Some(CourseLevel::Upper));
+        assert_eq!(level("CSE 200"), Some(CourseLevel::Graduate));
+        assert_eq!(level("EDS 361A"), Some(CourseLevel::Graduate));
+        assert_eq!(level("MED 401"), Some(CourseLevel::Graduate));
+        assert_eq!(level("CSE 500"), Some(CourseLevel::Graduate));
+        assert_eq!(level("CSE 600"), None);
+    }
+
+    #[sqlx::test]
+    async fn dry_run_keeps_cookies(conn: Pool<Postgres>) -> Result<()> {
+        use crate::cookies::{CookieStore, FileCookieStore};
+        use crate::testing;
+
+        let (session, cookies) = testing::session("dry-run").await;
+        session.renew().await?;
+        let saved = session.cookies().get();
+
+        get_all_courses(&conn, &session, false, true).await?;
+        // The mock refreshes the session cookie on every response
+        assert_ne!(session.cookies().get(), saved);
+        let store = FileCookieStore::new(testing::cookies_file(&cookies));
+        assert_eq!(store.load().await?, saved);
+        Ok(())
+    }
+}
@@ -28,6 +28,7 @@ pub struct Course {
 pub struct SectionId {
     pub sid: i32,
     pub course_code: String,
+    pub term: Option<String>,
 }
 
 #[derive(FromRow, Serialize, Debug, PartialEq)]
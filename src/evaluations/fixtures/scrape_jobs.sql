@@ -0,0 +1,7 @@
+-- Four CSE 120 sections waiting in the job queue.
+INSERT INTO units (id, name) VALUES (1, 'Computer Science and Engineering');
+INSERT INTO courses (code, name, unit_id)
+VALUES ('CSE 120', 'CSE 120 - Principles of Computer Operating Systems', 1);
+INSERT INTO sids (sid, course_code)
+VALUES (1, 'CSE 120'), (2, 'CSE 120'), (3, 'CSE 120'), (4, 'CSE 120');
+INSERT INTO scrape_jobs (sid) VALUES (1), (2), (3), (4);
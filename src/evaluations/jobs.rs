@@ -0,0 +1,395 @@
+use crate::database::Course;
+use crate::evaluations::queue::{self, StoredFilter};
+use crate::evaluations::{save_evals, SessionRejected};
+use crate::sessions::{self, Session};
+use crate::windows;
+use anyhow::{bail, Result};
+use clap::Args;
+use sqlx::{query, Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// How long a lease lasts without a heartbeat before other workers may take the job.
+const LEASE: Duration = Duration::from_secs(5 * 60);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// How often a waiting worker checks for new jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// A failed job waits this long times its attempts before it can be leased again.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How long a waiting worker holds off after its sessions stop.
+const SESSION_BACKOFF: Duration = Duration::from_secs(10 * 60);
+/// Jobs that have failed this many times are left for someone to look at.
+pub const MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    /// Jobs to lease at a time
+    #[arg(long, default_value_t = 50)]
+    pub batch: i64,
+    /// Keep polling for jobs instead of exiting when the queue is empty
+    #[arg(long)]
+    pub wait: bool,
+    /// Name recorded on leased jobs, the host name and process id by default
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
+/// Adds sections to `scrape_jobs`: pending ones, or with `refresh` stored ones. Sections
+/// already queued get the new priority and a fresh set of attempts unless a worker holds
+/// them.
+pub async fn enqueue(
+    conn: &Pool<Postgres>,
+    filter: &StoredFilter,
+    refresh: bool,
+    priority: i32,
+) -> Result<()> {
+    let work = if refresh {
+        queue::stored(conn, filter).await?
+    } else if filter.instructor.is_some() {
+        bail!("--instructor only matches stored evaluations, add --refresh");
+    } else {
+        queue::pending(conn, &filter.sections).await?
+    };
+    let sids = work
+        .into_iter()
+        .flat_map(|(_, sids)| sids)
+        .collect::<Vec<_>>();
+
+    let queued = query!(
+        "
+            INSERT INTO scrape_jobs (sid, priority)
+            SELECT sid, $2 FROM UNNEST($1::int[]) AS sid
+            ON CONFLICT (sid) DO UPDATE
+            SET priority = EXCLUDED.priority,
+                attempts = 0,
+                last_error = NULL,
+                leased_until = NULL,
+                done_at = NULL
+            WHERE scrape_jobs.leased_by IS NULL OR scrape_jobs.leased_until < NOW()
+        ",
+        &sids[..],
+        priority,
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+    info!(queued, sections = sids.len(), "queued sections");
+
+    Ok(())
+}
+
+/// Leases batches of jobs and scrapes them until the queue is empty, or forever with
+/// `--wait`. Any number of workers can share the queue.
+pub async fn work(conn: &Pool<Postgres>, args: &WorkerArgs) -> Result<()> {
+    let name = args.name.clone().unwrap_or_else(|| {
+        format!(
+            "{}-{}",
+            std::env::var("HOSTNAME").unwrap_or("worker".to_string()),
+            std::process::id()
+        )
+    });
+    let sessions = sessions::all(conn).await?;
+
+    loop {
+        windows::wait_for_open().await;
+        let work = lease(conn, &name, args.batch).await?;
+        if work.is_empty() {
+            if !args.wait {
+                info!(worker = name, "no jobs left");
+                return Ok(());
+            }
+            sleep(POLL_INTERVAL).await;
+            continue;
+        }
+        info!(
+            worker = name,
+            jobs = work.iter().map(|(_, sids)| sids.len()).sum::<usize>(),
+            "leased jobs"
+        );
+
+        match scrape(conn, &sessions, &name, work).await {
+            Err(e) if e.is::<SessionRejected>() && args.wait => {
+                warn!(worker = name, error = %format!("{e:#}"), "sessions stopped, backing off");
+                sleep(SESSION_BACKOFF).await;
+            }
+            res => res?,
+        }
+    }
+}
+
+/// Scrapes a leased batch and records how each job went. When the sessions stop, that
+/// says nothing about the jobs, so they are released without using up an attempt and
+/// the error is returned.
+async fn scrape(
+    conn: &Pool<Postgres>,
+    sessions: &[Arc<Session>],
+    name: &str,
+    work: Vec<(Course, Vec<i32>)>,
+) -> Result<()> {
+    let sids = work
+        .iter()
+        .flat_map(|(_, sids)| sids.iter().copied())
+        .collect::<Vec<_>>();
+    let heartbeat = tokio::spawn(heartbeat(conn.clone(), name.to_string()));
+    let failed = save_evals(conn, sessions, work, false).await;
+    heartbeat.abort();
+    let failed = match failed {
+        Ok(failed) => failed,
+        Err(e) if e.is::<SessionRejected>() => {
+            release(conn, name).await?;
+            return Err(e);
+        }
+        // Anything else counts against the whole batch, and only losing the database
+        // stops the worker
+        Err(e) => {
+            let error = format!("{e:#}");
+            warn!(worker = name, error, "batch failed, releasing its jobs");
+            sids.into_iter().map(|sid| (sid, error.clone())).collect()
+        }
+    };
+    finish(conn, name, &failed).await
+}
+
+/// Takes the highest-priority jobs nobody else holds, skipping rows other workers are
+/// leasing at the same moment.
+async fn lease(conn: &Pool<Postgres>, name: &str, batch: i64) -> Result<Vec<(Course, Vec<i32>)>> {
+    let rows = query!(
+        "
+            WITH leased AS (
+                UPDATE scrape_jobs
+                SET leased_by = $1,
+                    leased_until = NOW() + make_interval(secs => $2),
+                    attempts = attempts + 1
+                WHERE sid IN (
+                    SELECT sid FROM scrape_jobs
+                    WHERE done_at IS NULL
+                    AND attempts < $3
+                    AND (leased_until IS NULL OR leased_until < NOW())
+                    ORDER BY priority DESC, sid
+                    LIMIT $4
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING sid
+            )
+            SELECT leased.sid, courses.code, courses.name, courses.unit_id
+            FROM leased
+            INNER JOIN sids ON leased.sid = sids.sid
+            INNER JOIN courses ON sids.course_code = courses.code
+            ORDER BY courses.code, leased.sid
+        ",
+        name,
+        LEASE.as_secs_f64(),
+        MAX_ATTEMPTS,
+        batch,
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(queue::group_by_course(rows.into_iter().map(|row| {
+        (
+            Course {
+                code: row.code,
+                name: row.name,
+                unit_id: row.unit_id,
+            },
+            row.sid,
+        )
+    })))
+}
+
+/// Extends this worker's leases until the task is aborted.
+async fn heartbeat(conn: Pool<Postgres>, name: String) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let res = query!(
+            "
+                UPDATE scrape_jobs
+                SET leased_until = NOW() + make_interval(secs => $2)
+                WHERE leased_by = $1 AND done_at IS NULL
+            ",
+            name,
+            LEASE.as_secs_f64(),
+        )
+        .execute(&conn)
+        .await;
+        if let Err(e) = res {
+            warn!(error = %e, "could not extend leases");
+        }
+    }
+}
+
+/// Hands this worker's unfinished jobs back to the queue as if it had never leased them.
+async fn release(conn: &Pool<Postgres>, name: &str) -> Result<()> {
+    query!(
+        "
+            UPDATE scrape_jobs
+            SET attempts = attempts - 1, leased_by = NULL, leased_until = NULL
+            WHERE leased_by = $1 AND done_at IS NULL
+        ",
+        name,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Releases this worker's jobs, marking them done or recording the error and holding
+/// them back for a while before the next attempt.
+async fn finish(conn: &Pool<Postgres>, name: &str, failed: &[(i32, String)]) -> Result<()> {
+    let mut tx = conn.begin().await?;
+    query!(
+        "
+            UPDATE scrape_jobs
+            SET last_error = failed.error,
+                leased_by = NULL,
+                leased_until = NOW() + make_interval(secs => $4::float8 * attempts)
+            FROM UNNEST($2::int[], $3::text[]) AS failed (sid, error)
+            WHERE scrape_jobs.sid = failed.sid AND leased_by = $1
+        ",
+        name,
+        &failed.iter().map(|(sid, _)| *sid).collect::<Vec<_>>()[..],
+        &failed
+            .iter()
+            .map(|(_, error)| error.clone())
+            .collect::<Vec<_>>()[..],
+        RETRY_DELAY.as_secs_f64(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        "
+            UPDATE scrape_jobs
+            SET done_at = NOW(), last_error = NULL, leased_by = NULL, leased_until = NULL
+            WHERE leased_by = $1
+        ",
+        name,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn leased_sids(conn: &Pool<Postgres>, name: &str) -> Result<Vec<i32>> {
+        Ok(lease(conn, name, 10)
+            .await?
+            .into_iter()
+            .flat_map(|(_, sids)| sids)
+            .collect())
+    }
+
+    async fn expire_leases(conn: &Pool<Postgres>) -> Result<()> {
+        query!("UPDATE scrape_jobs SET leased_until = NOW() - INTERVAL '1 second'")
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("scrape_jobs"))]
+    async fn workers_lease_different_jobs(conn: Pool<Postgres>) -> Result<()> {
+        // Another worker in the middle of leasing jobs 1 and 2
+        let mut other = conn.begin().await?;
+        query!("SELECT sid FROM scrape_jobs WHERE sid <= 2 FOR UPDATE")
+            .fetch_all(&mut *other)
+            .await?;
+        assert_eq!(leased_sids(&conn, "a").await?, [3, 4]);
+        other.rollback().await?;
+
+        assert_eq!(leased_sids(&conn, "b").await?, [1, 2]);
+        assert!(leased_sids(&conn, "c").await?.is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("scrape_jobs"))]
+    async fn expired_leases_are_taken_over(conn: Pool<Postgres>) -> Result<()> {
+        assert_eq!(leased_sids(&conn, "a").await?, [1, 2, 3, 4]);
+        expire_leases(&conn).await?;
+        assert_eq!(leased_sids(&conn, "b").await?, [1, 2, 3, 4]);
+
+        // The first worker no longer holds the jobs, so finishing late doesn't touch them
+        finish(&conn, "a", &[]).await?;
+        let done =
+            query!("SELECT COUNT(*) AS \"count!\" FROM scrape_jobs WHERE done_at IS NOT NULL")
+                .fetch_one(&conn)
+                .await?;
+        assert_eq!(done.count, 0);
+
+        finish(&conn, "b", &[(1, "no luck".to_string())]).await?;
+        let jobs = query!(
+            "
+                SELECT sid, done_at IS NOT NULL AS \"done!\", last_error
+                FROM scrape_jobs
+                ORDER BY sid
+            "
+        )
+        .fetch_all(&conn)
+        .await?;
+        assert_eq!(
+            jobs.into_iter()
+                .map(|job| (job.sid, job.done, job.last_error))
+                .collect::<Vec<_>>(),
+            [
+                (1, false, Some("no luck".to_string())),
+                (2, true, None),
+                (3, true, None),
+                (4, true, None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("scrape_jobs"))]
+    async fn stopped_sessions_dont_use_up_attempts(conn: Pool<Postgres>) -> Result<()> {
+        // Renewing gives it a cookie the mock SET site doesn't take as a session
+        let (session, _cookies) = testing::command_session("worker", "echo other=1").await;
+        let work = lease(&conn, "a", 10).await?;
+        let e = scrape(&conn, &[Arc::new(session)], "a", work)
+            .await
+            .unwrap_err();
+        assert!(e.is::<SessionRejected>(), "{e:#}");
+
+        let jobs = query!("SELECT attempts, leased_by, last_error FROM scrape_jobs")
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(jobs.len(), 4);
+        for job in jobs {
+            assert_eq!(job.attempts, 0);
+            assert_eq!(job.leased_by, None);
+            assert_eq!(job.last_error, None);
+        }
+        assert_eq!(leased_sids(&conn, "b").await?, [1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("scrape_jobs"))]
+    async fn failing_jobs_stop_at_max_attempts(conn: Pool<Postgres>) -> Result<()> {
+        query!("DELETE FROM scrape_jobs WHERE sid > 1")
+            .execute(&conn)
+            .await?;
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(leased_sids(&conn, "a").await?, [1]);
+            finish(&conn, "a", &[(1, "no luck".to_string())]).await?;
+            // Held back for a while after failing
+            assert!(leased_sids(&conn, "a").await?.is_empty());
+            expire_leases(&conn).await?;
+        }
+
+        assert!(leased_sids(&conn, "a").await?.is_empty());
+        let job = query!("SELECT attempts, done_at IS NOT NULL AS \"done!\" FROM scrape_jobs")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(job.attempts, MAX_ATTEMPTS);
+        assert!(!job.done);
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
+pub mod jobs;
+mod page;
 mod parser;
+pub mod queue;
 pub mod sids;
+pub mod validate;
 
+pub(crate) use page::is_login_page;
 pub use parser::*;
 use sqlx::{query, Pool, Postgres};
 
@@ -41,3 +46,189 @@ async fn get_or_create_instructor_id(conn: &Pool<Postgres>, name: String) -> Res
 
     Ok(id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::queue::{self, EvalFilter};
+    use super::sids::save_all_sids;
+    use super::{save_evals, SessionRejected};
+    use crate::courses::get_all_courses;
+    use crate::database::Course;
+    use crate::sessions::Session;
+    use crate::testing;
+    use anyhow::Result;
+    use sqlx::{query, Pool, Postgres};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn cse_120() -> Course {
+        Course {
+            code: "CSE 120".to_string(),
+            name: "CSE 120 - Principles of Computer Operating Systems".to_string(),
+            unit_id: 1,
+        }
+    }
+
+    /// Recorded from `mock-set` on its default port with `--record` while running courses,
+    /// sids and evals.
+    async fn replaying(name: &str) -> (Arc<Session>, TempDir) {
+        let cassette = testing::fixtures().join("cassettes/pipeline");
+        let (session, cookies) = testing::offline_session(name).await;
+        let session = session.replaying("http://127.0.0.1:5002", cassette);
+        (Arc::new(session), cookies)
+    }
+
+    #[sqlx::test]
+    async fn scrapes_the_mock_site(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = testing::session("pipeline").await;
+        let session = Arc::new(session);
+        session.renew().await?;
+
+        get_all_courses(&conn, &session, false, false).await?;
+        let courses = query!("SELECT code FROM courses ORDER BY code")
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(
+            courses.into_iter().map(|row| row.code).collect::<Vec<_>>(),
+            ["CSE 120", "CSE 8A"]
+        );
+
+        save_all_sids(&conn, &session, false).await?;
+        let work = queue::pending(&conn, &EvalFilter::default()).await?;
+        assert_eq!(work.iter().map(|(_, sids)| sids.len()).sum::<usize>(), 3);
+
+        let failed = save_evals(&conn, &[session], work, false).await?;
+        assert!(failed.is_empty(), "failed: {failed:?}");
+        let evals = query!("SELECT sid, course_code FROM evaluations ORDER BY sid")
+            .fetch_all(&conn)
+            .await?;
+        assert_eq!(
+            evals
+                .into_iter()
+                .map(|row| (row.sid, row.course_code))
+                .collect::<Vec<_>>(),
+            [
+                (100001, "CSE 120".to_string()),
+                (100002, "CSE 120".to_string()),
+                (100003, "CSE 8A".to_string()),
+            ]
+        );
+        assert!(queue::pending(&conn, &EvalFilter::default())
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn replays_sids_and_evals(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = replaying("replay-pipeline").await;
+        get_all_courses(&conn, &session, false, false).await?;
+        save_all_sids(&conn, &session, false).await?;
+        let sids = query!("SELECT COUNT(*) AS \"count!\" FROM sids")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(sids.count, 3);
+
+        let work = queue::pending(&conn, &EvalFilter::default()).await?;
+        let failed = save_evals(&conn, &[session], work, false).await?;
+        assert!(failed.is_empty(), "failed: {failed:?}");
+        let evals = query!("SELECT COUNT(*) AS \"count!\" FROM evaluations")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(evals.count, 3);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn pending_sections_match_terms(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = replaying("replay-terms").await;
+        get_all_courses(&conn, &session, false, false).await?;
+        save_all_sids(&conn, &session, false).await?;
+
+        let filter = EvalFilter {
+            term: Some("winter*".to_string()),
+            ..EvalFilter::default()
+        };
+        let work = queue::pending(&conn, &filter).await?;
+        assert_eq!(
+            work.iter()
+                .map(|(course, sids)| (course.code.as_str(), sids.as_slice()))
+                .collect::<Vec<_>>(),
+            [("CSE 120", &[100002][..])]
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unrecorded_searches_are_given_up_on(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = replaying("replay-unsearched").await;
+        get_all_courses(&conn, &session, false, false).await?;
+        query!(
+            "
+                INSERT INTO courses (code, name, unit_id)
+                VALUES ('CSE 999', 'CSE 999 - Unrecorded', 1)
+            "
+        )
+        .execute(&conn)
+        .await?;
+
+        let e = save_all_sids(&conn, &session, false).await.unwrap_err();
+        assert_eq!(e.to_string(), "Could not search 1 courses");
+        // What the other searches found is kept
+        let sids = query!("SELECT COUNT(*) AS \"count!\" FROM sids")
+            .fetch_one(&conn)
+            .await?;
+        assert_eq!(sids.count, 3);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn replay_failures_dont_renew(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = replaying("replay-missing").await;
+        let course = cse_120();
+        // More unrecorded sids than it takes to renew a failing session, which this one
+        // can't do
+        let sids = (900001..=900005).collect::<Vec<_>>();
+        let failed = save_evals(&conn, &[session], vec![(course, sids)], true).await?;
+        assert_eq!(failed.len(), 5);
+        assert!(failed[0].1.contains("No recorded response"));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn stops_a_session_renewing_doesnt_help(conn: Pool<Postgres>) -> Result<()> {
+        // Renewing gives it a cookie the mock SET site doesn't take as a session
+        let (session, _cookies) = testing::command_session("rejected", "echo other=1").await;
+        let session = Arc::new(session);
+        let work = vec![(cse_120(), vec![100001, 100002])];
+        let e = save_evals(&conn, &[session], work, true).await.unwrap_err();
+        assert!(e.is::<SessionRejected>(), "{e:#}");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn other_sessions_take_over_from_a_stopped_one(conn: Pool<Postgres>) -> Result<()> {
+        let (rejected, _rejected_cookies) =
+            testing::command_session("handed-over", "echo other=1").await;
+        let rejected = Arc::new(rejected);
+        let (good, _good_cookies) = testing::session("takes-over").await;
+        let good = Arc::new(good);
+        good.renew().await?;
+        let work = vec![(cse_120(), vec![100001, 100002])];
+        let failed = save_evals(&conn, &[rejected, good], work, true).await?;
+        assert!(failed.is_empty(), "failed: {failed:?}");
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn failed_renewal_fails_the_section(conn: Pool<Postgres>) -> Result<()> {
+        let (session, _cookies) = testing::offline_session("unrenewable").await;
+        let session = Arc::new(session);
+        let work = vec![(cse_120(), vec![100001, 100002])];
+        // The first section fails and the session carries on to the second, which gets the
+        // login page too soon to renew again
+        let e = save_evals(&conn, &[session], work, true).await.unwrap_err();
+        assert!(e.is::<SessionRejected>(), "{e:#}");
+        Ok(())
+    }
+}
@@ -0,0 +1,651 @@
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use thiserror::Error;
+
+const TITLE: &str = "#ContentPlaceHolder1_EvalsContentPlaceHolder_lblSummaryTitle > p";
+const STATS: &str = "#ContentPlaceHolder1_EvalsContentPlaceHolder_lblSummaryTitle > p:nth-child(2)";
+const EXPECTED_GRADES: &str =
+    "#ContentPlaceHolder1_EvalsContentPlaceHolder_tblExpectedGrades > tbody > tr";
+const ACTUAL_GRADES: &str =
+    "#ContentPlaceHolder1_EvalsContentPlaceHolder_tblGradesReceived > tbody > tr";
+
+/// Where the hours question usually sits on pages with the long layout.
+const LONG_HOURS_INDEX: u32 = 14;
+/// Sections where fewer than this share of students answered are flagged.
+const LOW_RESPONSE_RATE: f64 = 0.25;
+/// Sections with fewer responses than this are flagged.
+const FEW_RESPONSES: i32 = 5;
+
+/// How many characters of the page an error quotes.
+const SNIPPET_LEN: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Evaluation {
+    pub sid: i32,
+    pub section_name: String,
+    pub course_code: String,
+    pub term: String,
+    pub instructor: String,
+
+    pub enrollment: i32,
+    pub responses: i32,
+
+    pub class_helped_understanding: Vec<i32>,
+    pub assignments_helped_understanding: Vec<i32>,
+    pub fair_exams: Vec<i32>,
+    pub timely_feedback: Vec<i32>,
+    pub developed_understanding: Vec<i32>,
+    pub engaging: Vec<i32>,
+    pub communication: Vec<i32>,
+    pub help_opportunities: Vec<i32>,
+    pub effective_methods: Vec<i32>,
+    pub timeliness: Vec<i32>,
+    pub welcoming: Vec<i32>,
+    pub materials: Vec<i32>,
+    pub hours: Vec<i32>,
+    pub expected_grades: Vec<i32>,
+    pub actual_grades: Vec<i32>,
+    pub quality_flags: Vec<QualityFlag>,
+}
+
+/// Reasons to be careful with an evaluation's numbers, worked out when it is scraped.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityFlag {
+    /// The page had no expected grades, so there is no expected GPA
+    MissingExpectedGrades,
+    /// Fewer than a quarter of enrolled students responded
+    LowResponseRate,
+    /// Fewer than five students responded
+    FewResponses,
+    /// Hours were asked in four wide buckets rather than eleven narrow ones
+    CoarseHours,
+    /// The hours question wasn't where it usually is and had to be searched for
+    LayoutFallback,
+}
+
+impl QualityFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityFlag::MissingExpectedGrades => "missing_expected_grades",
+            QualityFlag::LowResponseRate => "low_response_rate",
+            QualityFlag::FewResponses => "few_responses",
+            QualityFlag::CoarseHours => "coarse_hours",
+            QualityFlag::LayoutFallback => "layout_fallback",
+        }
+    }
+}
+
+/// Why a SETSummary page could not be turned into an [`Evaluation`]. Each error names
+/// the selector that failed and quotes the part of the page it was looking at.
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("sid {sid}: got the login page, the session has expired")]
+    LoginPage { sid: i32 },
+    #[error("sid {sid}: no title at `{selector}` in \"{snippet}\"")]
+    MissingTitle {
+        sid: i32,
+        selector: &'static str,
+        snippet: String,
+    },
+    #[error("sid {sid}: could not find the instructor in `{selector}`: {snippet}")]
+    MalformedInstructor {
+        sid: i32,
+        selector: &'static str,
+        snippet: String,
+    },
+    #[error("sid {sid}: could not find the term and section in `{selector}`: {snippet}")]
+    MalformedTerm {
+        sid: i32,
+        selector: &'static str,
+        snippet: String,
+    },
+    #[error("sid {sid}: could not find response and enrollment counts in `{selector}`: {snippet}")]
+    MalformedStats {
+        sid: i32,
+        selector: &'static str,
+        snippet: String,
+    },
+    #[error("sid {sid}: no table at `{selector}` in \"{snippet}\"")]
+    MissingTable {
+        sid: i32,
+        selector: &'static str,
+        snippet: String,
+    },
+    #[error("sid {sid}: no question at `{selector}` in \"{snippet}\"")]
+    MissingScale {
+        sid: i32,
+        selector: String,
+        snippet: String,
+    },
+    #[error("sid {sid}: matched neither questionnaire layout: \"{snippet}\"")]
+    UnknownLayout { sid: i32, snippet: String },
+    #[error("sid {sid}: expected a number at `{selector}` but found \"{snippet}\"")]
+    NonNumericCell {
+        sid: i32,
+        selector: String,
+        snippet: String,
+    },
+}
+
+impl ParseError {
+    /// A short name for the kind of failure, for grouping failures in reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::LoginPage { .. } => "login page",
+            ParseError::MissingTitle { .. } => "missing title",
+            ParseError::MalformedInstructor { .. } => "malformed instructor",
+            ParseError::MalformedTerm { .. } => "malformed term",
+            ParseError::MalformedStats { .. } => "malformed stats",
+            ParseError::MissingTable { .. } => "missing table",
+            ParseError::MissingScale { .. } => "missing question",
+            ParseError::UnknownLayout { .. } => "unknown layout",
+            ParseError::NonNumericCell { .. } => "non-numeric cell",
+        }
+    }
+}
+
+/// What parsing a fixture page should give, kept next to it in `tests/fixtures` as JSON.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Expected {
+    Parsed(Box<Evaluation>),
+    Failed { course_code: String, error: String },
+}
+
+impl Expected {
+    pub fn new(result: Result<Evaluation, ParseError>, course_code: &str) -> Expected {
+        match result {
+            Ok(eval) => Expected::Parsed(Box::new(eval)),
+            Err(e) => Expected::Failed {
+                course_code: course_code.to_string(),
+                error: e.kind().to_string(),
+            },
+        }
+    }
+}
+
+impl Evaluation {
+    /// Whether every question has as many answer buckets as the page layouts allow.
+    #[cfg(any(test, fuzzing))]
+    pub fn has_valid_shape(&self) -> bool {
+        let scales = [
+            &self.class_helped_understanding,
+            &self.assignments_helped_understanding,
+            &self.fair_exams,
+            &self.timely_feedback,
+            &self.developed_understanding,
+            &self.engaging,
+            &self.communication,
+            &self.help_opportunities,
+            &self.effective_methods,
+            &self.timeliness,
+            &self.welcoming,
+        ];
+        scales.iter().all(|scale| scale.len() == 6)
+            && self.materials.len() == 5
+            && matches!(self.hours.len(), 4 | 11)
+    }
+}
+
+/// Strips a saved page of session state, scripts and email addresses so it can be
+/// checked in as a fixture.
+pub fn sanitize(page: &str) -> String {
+    let scripts = Regex::new(r"(?is)<script\b.*?</script>").unwrap();
+    let state = Regex::new(
+        r#"(?i)(id="__(?:VIEWSTATE|VIEWSTATEGENERATOR|EVENTVALIDATION)"\s+value=")[^"]*""#,
+    )
+    .unwrap();
+    let emails = Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap();
+
+    let page = scripts.replace_all(page, "");
+    let page = state.replace_all(&page, "$1\"");
+    emails
+        .replace_all(&page, "redacted@example.com")
+        .into_owned()
+}
+
+/// Collapses whitespace and cuts `text` down to something that fits in an error message.
+fn snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+fn page_snippet(html: &Html) -> String {
+    snippet(&html.root_element().text().collect::<String>())
+}
+
+/// Whether the SET site sent its single sign-on page instead of an evaluation.
+pub(crate) fn is_login_page(html: &Html) -> bool {
+    let title = Selector::parse("title").unwrap();
+    let password = Selector::parse("input[type=password], form#login").unwrap();
+    html.select(&password).next().is_some()
+        || html.select(&title).any(|title| {
+            let title = title.text().collect::<String>().to_lowercase();
+            title.contains("sign-on") || title.contains("sign on") || title.contains("log in")
+        })
+}
+
+/// Reads an evaluation out of a SETSummary page. Doesn't touch the network or the database.
+pub fn parse(html: &Html, sid: i32, course_code: &str) -> Result<Evaluation, ParseError> {
+    let title = html
+        .select(&Selector::parse(TITLE).unwrap())
+        .next()
+        .ok_or_else(|| match is_login_page(html) {
+            true => ParseError::LoginPage { sid },
+            false => ParseError::MissingTitle {
+                sid,
+                selector: TITLE,
+                snippet: page_snippet(html),
+            },
+        })?;
+    let mut lines = title.children().filter_map(|child| child.value().as_text());
+
+    let instructor = lines
+        .next()
+        .and_then(|s| {
+            s.rmatch_indices(',')
+                .nth(1)
+                .and_then(|(i, _)| s.get(i + 2..))
+        })
+        .ok_or_else(|| ParseError::MalformedInstructor {
+            sid,
+            selector: TITLE,
+            snippet: snippet(&title.html()),
+        })?
+        .trim();
+
+    let malformed_term = || ParseError::MalformedTerm {
+        sid,
+        selector: TITLE,
+        snippet: snippet(&title.html()),
+    };
+    let (term, second) = lines
+        .next()
+        .and_then(|s| s.split_once(','))
+        .ok_or_else(malformed_term)?;
+    let section_name = Regex::new(r"Section ID .*? \((.*?)\)")
+        .unwrap()
+        .captures(second)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+        .ok_or_else(malformed_term)?;
+
+    let (responses, enrollment) = {
+        let malformed_stats = |snippet| ParseError::MalformedStats {
+            sid,
+            selector: STATS,
+            snippet,
+        };
+        let stats = html
+            .select(&Selector::parse(STATS).unwrap())
+            .next()
+            .ok_or_else(|| malformed_stats(page_snippet(html)))?;
+        let mut counts = stats.children().filter_map(|child| {
+            child
+                .value()
+                .as_text()
+                .and_then(|text| text.split_once(": "))
+                .and_then(|(_, s)| s.trim().parse::<i32>().ok())
+        });
+        match (counts.next(), counts.next()) {
+            (Some(responses), Some(enrollment)) => (responses, enrollment),
+            _ => return Err(malformed_stats(snippet(&stats.html()))),
+        }
+    };
+
+    let expected_grades = parse_grades_table(html, sid, EXPECTED_GRADES).unwrap_or_default();
+    let actual_grades = parse_grades_table(html, sid, ACTUAL_GRADES)?;
+
+    let layout = parse_hours_materials(html, sid)?;
+
+    let scales = layout
+        .scales
+        .map(|i| parse_scale::<6>(html, sid, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut quality_flags = Vec::new();
+    if expected_grades.is_empty() {
+        quality_flags.push(QualityFlag::MissingExpectedGrades);
+    }
+    if (responses as f64) < enrollment as f64 * LOW_RESPONSE_RATE {
+        quality_flags.push(QualityFlag::LowResponseRate);
+    }
+    if responses < FEW_RESPONSES {
+        quality_flags.push(QualityFlag::FewResponses);
+    }
+    if layout.hours.len() == 4 {
+        quality_flags.push(QualityFlag::CoarseHours);
+    }
+    if layout.shifted {
+        quality_flags.push(QualityFlag::LayoutFallback);
+    }
+
+    Ok(Evaluation {
+        sid,
+        section_name: section_name.to_string(),
+        course_code: course_code.to_string(),
+        term: term.trim().to_string(),
+        instructor: instructor.to_string(),
+        enrollment,
+        responses,
+        class_helped_understanding: scales[0].clone(),
+        assignments_helped_understanding: scales[1].clone(),
+        fair_exams: scales[2].clone(),
+        timely_feedback: scales[3].clone(),
+        developed_understanding: scales[4].clone(),
+        engaging: scales[5].clone(),
+        communication: scales[6].clone(),
+        help_opportunities: scales[7].clone(),
+        effective_methods: scales[8].clone(),
+        timeliness: scales[9].clone(),
+        welcoming: scales[10].clone(),
+        materials: layout.materials,
+        hours: layout.hours,
+        expected_grades,
+        actual_grades,
+        quality_flags,
+    })
+}
+
+struct Layout {
+    hours: Vec<i32>,
+    materials: Vec<i32>,
+    /// Indices of the remaining questions
+    scales: Range<u32>,
+    /// Whether the hours question was found somewhere other than its usual place
+    shifted: bool,
+}
+
+/// Finds the hours and materials questions, which come either after the other questions
+/// with 11 hour buckets or before them with 4, and returns where the remaining questions
+/// are.
+fn parse_hours_materials(html: &Html, sid: i32) -> Result<Layout, ParseError> {
+    for long_hours_idx in LONG_HOURS_INDEX..=20 {
+        if let Ok(hours) = parse_scale::<11>(html, sid, long_hours_idx) {
+            return Ok(Layout {
+                hours,
+                materials: parse_scale::<5>(html, sid, long_hours_idx - 1)?,
+                scales: 0..11,
+                shifted: long_hours_idx != LONG_HOURS_INDEX,
+            });
+        }
+    }
+    let short = || -> Result<_, ParseError> {
+        Ok(Layout {
+            hours: parse_scale::<4>(html, sid, 2)?,
+            materials: parse_scale::<5>(html, sid, 1)?,
+            scales: 4..15,
+            shifted: false,
+        })
+    };
+    short().map_err(|e| match e {
+        ParseError::MissingScale { .. } => ParseError::UnknownLayout {
+            sid,
+            snippet: page_snippet(html),
+        },
+        e => e,
+    })
+}
+
+fn parse_grades_table(
+    html: &Html,
+    sid: i32,
+    selector: &'static str,
+) -> Result<Vec<i32>, ParseError> {
+    let td_selector = Selector::parse("td").unwrap();
+
+    html.select(&Selector::parse(selector).unwrap())
+        .next()
+        .ok_or_else(|| ParseError::MissingTable {
+            sid,
+            selector,
+            snippet: page_snippet(html),
+        })?
+        .select(&td_selector)
+        .map(|col| parse_cell(col, sid, selector))
+        .collect()
+}
+
+fn parse_scale<const N: usize>(html: &Html, sid: i32, index: u32) -> Result<Vec<i32>, ParseError> {
+    let mut result = vec![0; N];
+    for (i, item) in result.iter_mut().enumerate() {
+        let selector = format!(
+            "#ContentPlaceHolder1_EvalsContentPlaceHolder_rptQuestionnaire_rptChoices_{index}_rbSelect_{i}"
+        );
+        let cell = html
+            .select(&Selector::parse(&selector).unwrap())
+            .next()
+            .ok_or_else(|| ParseError::MissingScale {
+                sid,
+                selector: selector.clone(),
+                snippet: page_snippet(html),
+            })?;
+        let text: String = cell.text().take(1).collect();
+        *item = text.parse().map_err(|_| ParseError::NonNumericCell {
+            sid,
+            selector,
+            snippet: snippet(&text),
+        })?;
+    }
+    Ok(result)
+}
+
+fn parse_cell(cell: ElementRef, sid: i32, selector: &str) -> Result<i32, ParseError> {
+    let text = cell.text().collect::<String>();
+    text.trim().parse().map_err(|_| ParseError::NonNumericCell {
+        sid,
+        selector: format!("{selector} > td"),
+        snippet: snippet(&text),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use std::fs;
+    use std::path::Path;
+
+    /// Parses every page in `tests/fixtures/SETSummary` and compares the result with the
+    /// JSON next to it, which is checked by hand against the page. Set `UPDATE_GOLDEN=1` to
+    /// rewrite the JSON after a deliberate change, then check the diff the same way.
+    #[test]
+    fn fixtures_match_expected() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/SETSummary");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut checked = 0;
+
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "html") {
+                continue;
+            }
+            let sid = path.file_stem().unwrap().to_str().unwrap().parse().unwrap();
+            let json = path.with_extension("json");
+            let contents = fs::read_to_string(&json)
+                .unwrap_or_else(|_| panic!("{} has no expected JSON", path.display()));
+            // Read on its own so that JSON from before a change to `Evaluation` can still
+            // be rewritten
+            let value: serde_json::Value = serde_json::from_str(&contents).unwrap();
+            let course_code = value["course_code"].as_str().unwrap();
+
+            let html = Html::parse_document(&fs::read_to_string(&path).unwrap());
+            let actual = Expected::new(parse(&html, sid, course_code), course_code);
+            if update {
+                fs::write(&json, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+            } else {
+                let expected: Expected = serde_json::from_value(value).unwrap();
+                assert_eq!(actual, expected, "{}", path.display());
+            }
+            checked += 1;
+        }
+
+        assert!(checked > 0, "no fixtures in {}", dir.display());
+    }
+
+    fn fixture(sid: i32) -> Evaluation {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(format!("tests/fixtures/SETSummary/{sid}.html"));
+        let html = Html::parse_document(&fs::read_to_string(path).unwrap());
+        parse(&html, sid, "CSE 120").unwrap()
+    }
+
+    /// Every question is answered once per response, which holds on any real page whatever
+    /// the parser makes of it.
+    #[test]
+    fn fixture_answers_add_up_to_responses() {
+        for sid in [100001, 100002, 100003] {
+            let eval = fixture(sid);
+            let questions = [
+                &eval.class_helped_understanding,
+                &eval.assignments_helped_understanding,
+                &eval.fair_exams,
+                &eval.timely_feedback,
+                &eval.developed_understanding,
+                &eval.engaging,
+                &eval.communication,
+                &eval.help_opportunities,
+                &eval.effective_methods,
+                &eval.timeliness,
+                &eval.welcoming,
+                &eval.materials,
+                &eval.hours,
+            ];
+            for answers in questions {
+                assert_eq!(answers.iter().sum::<i32>(), eval.responses, "{sid}");
+            }
+        }
+    }
+
+    #[test]
+    fn fixtures_cover_both_hours_layouts() {
+        assert_eq!(fixture(100001).hours.len(), 4);
+        assert_eq!(fixture(100002).hours.len(), 11);
+    }
+
+    #[test]
+    fn fixtures_cover_missing_expected_grades() {
+        assert!(fixture(100003).expected_grades.is_empty());
+        assert!(!fixture(100003).actual_grades.is_empty());
+    }
+
+    #[test]
+    fn login_page_is_reported() {
+        let html = Html::parse_document(
+            "<html><head><title>UC San Diego Single Sign-On</title></head><body></body></html>",
+        );
+        assert!(matches!(
+            parse(&html, 1, "CSE 120"),
+            Err(ParseError::LoginPage { sid: 1 })
+        ));
+    }
+
+    #[test]
+    fn sanitize_strips_session_state() {
+        let page = r#"<input type="hidden" name="__VIEWSTATE" id="__VIEWSTATE" value="abc123" />
+            <script>var user = "x";</script><p>jdoe@ucsd.edu</p>"#;
+        let clean = sanitize(page);
+        assert!(clean.contains(r#"id="__VIEWSTATE" value="""#));
+        assert!(!clean.contains("abc123"));
+        assert!(!clean.contains("<script>"));
+        assert!(!clean.contains("jdoe@ucsd.edu"));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Mutation {
+        Delete(Index, usize),
+        Insert(Index, String),
+        Replace(Index, usize, String),
+        Truncate(Index),
+    }
+
+    /// Text that tends to upset the parser: separators it splits on, numbers, markup and
+    /// anything else.
+    fn noise() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[0-9,:() -]{0,8}",
+            ".{0,8}",
+            Just(",".to_string()),
+            Just(": ".to_string()),
+            Just("</p>".to_string()),
+            Just("<td>x</td>".to_string()),
+            Just("-2147483649".to_string()),
+        ]
+    }
+
+    fn mutation() -> impl Strategy<Value = Mutation> {
+        prop_oneof![
+            (any::<Index>(), 1..64usize).prop_map(|(at, len)| Mutation::Delete(at, len)),
+            (any::<Index>(), noise()).prop_map(|(at, text)| Mutation::Insert(at, text)),
+            (any::<Index>(), 1..16usize, noise())
+                .prop_map(|(at, len, text)| Mutation::Replace(at, len, text)),
+            any::<Index>().prop_map(Mutation::Truncate),
+        ]
+    }
+
+    fn mutate(page: &str, mutations: &[Mutation]) -> String {
+        let mut chars = page.chars().collect::<Vec<_>>();
+        for mutation in mutations {
+            let len = chars.len().max(1);
+            match mutation {
+                Mutation::Delete(at, n) => {
+                    let at = at.index(len).min(chars.len());
+                    chars.drain(at..(at + n).min(chars.len()));
+                }
+                Mutation::Insert(at, text) => {
+                    let at = at.index(len).min(chars.len());
+                    chars.splice(at..at, text.chars());
+                }
+                Mutation::Replace(at, n, text) => {
+                    let at = at.index(len).min(chars.len());
+                    chars.splice(at..(at + n).min(chars.len()), text.chars());
+                }
+                Mutation::Truncate(at) => chars.truncate(at.index(len)),
+            }
+        }
+        chars.into_iter().collect()
+    }
+
+    fn fixture_pages() -> Vec<String> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/SETSummary");
+        let mut pages = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect::<Vec<_>>();
+        pages.sort();
+        pages
+    }
+
+    proptest! {
+        /// Damaged pages must come back as a well-formed evaluation or a `ParseError`,
+        /// never a panic.
+        #[test]
+        fn mutated_fixtures_never_panic(
+            page in any::<Index>(),
+            mutations in prop::collection::vec(mutation(), 1..8),
+        ) {
+            let pages = fixture_pages();
+            let page = mutate(&pages[page.index(pages.len())], &mutations);
+            if let Ok(eval) = parse(&Html::parse_document(&page), 1, "CSE 120") {
+                prop_assert!(eval.has_valid_shape(), "{eval:?}");
+            }
+        }
+
+        #[test]
+        fn mutated_titles_never_panic(line in ".{0,40}", extra in ".{0,40}") {
+            let page = format!(
+                r#"<span id="ContentPlaceHolder1_EvalsContentPlaceHolder_lblSummaryTitle"><p>{line}<br>{extra}</p></span>"#
+            );
+            let _ = parse(&Html::parse_document(&page), 1, "CSE 120");
+        }
+    }
+}
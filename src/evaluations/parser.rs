@@ -1,302 +1,438 @@
+use crate::auth::renewal;
 use crate::common;
 use crate::database::Course;
+use crate::evaluations::page::{parse, sanitize, Evaluation, Expected, ParseError};
 use crate::evaluations::{get_or_create_instructor_id, get_or_create_term_id};
-use anyhow::{anyhow, Result};
+use crate::sessions::Session;
+use crate::upstream::Upstream;
+use crate::windows;
+use anyhow::{anyhow, bail, Result};
+use futures::future::join_all;
+use futures::FutureExt;
 use indicatif::ProgressBar;
-use regex::Regex;
-use reqwest::Client;
-use scraper::{Html, Selector};
+use scraper::Html;
 use sqlx::{query, Pool, Postgres};
-use std::ops::Range;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::time::Instant;
+use tracing::{field, info, info_span, instrument, warn, Instrument, Span};
 
+/// Consecutive failures after which a session gets new cookies, in case the site has
+/// quietly dropped it.
+const RENEW_AFTER_FAILURES: u32 = 3;
+/// A session is renewed at most once in this long. Getting the login page again sooner
+/// means renewing doesn't help, and the session stops.
+const RENEW_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+/// The SET site keeps sending a session the login page even though it was just renewed.
+#[derive(thiserror::Error, Debug)]
+#[error("Session {0} still gets the login page after renewing, stopping it")]
+pub struct SessionRejected(String);
+
+/// Results for one course, reported once all of its sids are done.
+struct CourseProgress<'a> {
+    course: &'a Course,
+    span: Span,
+    remaining: usize,
+    saved: u64,
+    parsed: Vec<Evaluation>,
+    failures: Vec<(i32, anyhow::Error)>,
+}
+
+enum Outcome {
+    Saved(u64),
+    Parsed(Box<Evaluation>),
+    Failed(anyhow::Error),
+}
+
+fn failure_kind(e: &anyhow::Error) -> &'static str {
+    e.downcast_ref::<ParseError>()
+        .map_or("request", ParseError::kind)
+}
+
+/// Scrapes every sid in `work`, spreading them over `sessions` so each scrapes in parallel
+/// within its own rate budget. Returns the sids that failed, with their errors.
+#[instrument(name = "run", skip_all, fields(stage = "evals", sessions = sessions.len(), dry_run))]
 pub async fn save_evals(
     conn: &Pool<Postgres>,
-    course: &Course,
-    sids: Vec<i32>,
+    sessions: &[Arc<Session>],
+    work: Vec<(Course, Vec<i32>)>,
+    dry_run: bool,
+) -> Result<Vec<(i32, String)>> {
+    let _renewals = sessions
+        .iter()
+        .map(|session| renewal::spawn(session.clone()))
+        .collect::<Vec<_>>();
+
+    let pb = common::progress_bar(work.iter().map(|(_, sids)| sids.len() as u64).sum());
+    let courses = work
+        .iter()
+        .map(|(course, sids)| {
+            info!(sids = sids.len(), "found sids for {}", course.code);
+            Mutex::new(CourseProgress {
+                course,
+                span: info_span!("course", code = %course.code, sids = sids.len()),
+                remaining: sids.len(),
+                saved: 0,
+                parsed: Vec::new(),
+                failures: Vec::new(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let queue = Mutex::new(
+        work.iter()
+            .enumerate()
+            .flat_map(|(i, (_, sids))| sids.iter().map(move |&sid| (i, sid)))
+            .collect::<VecDeque<_>>(),
+    );
+
+    // A session that stops hands its sids back to the queue, and the rest go round again
+    // to pick them up
+    let mut alive = sessions.to_vec();
+    let mut failed = Vec::new();
+    let mut last_error = None;
+    while !alive.is_empty() && !queue.lock().unwrap().is_empty() {
+        let results = join_all(alive.drain(..).map(|session| {
+            drain(conn, session.clone(), &queue, &courses, &pb, dry_run)
+                .map(move |result| (session, result))
+        }))
+        .await;
+        for (session, result) in results {
+            match result {
+                Ok(session_failed) => {
+                    failed.extend(session_failed);
+                    alive.push(session);
+                }
+                Err(e) => {
+                    warn!(session = %session.name, error = %format!("{e:#}"), "session stopped, handing its sections to the others");
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+    if let Some(e) = last_error.filter(|_| alive.is_empty()) {
+        return Err(e.context("Every session stopped"));
+    }
+
+    pb.finish();
+    Ok(failed)
+}
+
+/// Takes sids off the shared queue until it's empty, scraping them with one session.
+async fn drain(
+    conn: &Pool<Postgres>,
+    session: Arc<Session>,
+    queue: &Mutex<VecDeque<(usize, i32)>>,
+    courses: &[Mutex<CourseProgress<'_>>],
     pb: &ProgressBar,
-) -> Result<bool> {
-    let client = common::client()?;
+    dry_run: bool,
+) -> Result<Vec<(i32, String)>> {
+    let mut failing = 0;
+    let mut failed = Vec::new();
 
-    let mut saved = 0;
-    let mut failures = Vec::new();
+    loop {
+        windows::wait_for_open().await;
+        let Some((i, sid)) = queue.lock().unwrap().pop_front() else {
+            break;
+        };
+        let (course, course_span) = {
+            let progress = courses[i].lock().unwrap();
+            (progress.course, progress.span.clone())
+        };
 
-    pb.set_length(sids.len() as u64);
+        let span = info_span!(
+            parent: &course_span,
+            "sid",
+            sid,
+            session = %session.name,
+            retries = field::Empty,
+            renewed = field::Empty,
+        );
+        let outcome = async {
+            let start = Instant::now();
+            let outcome = match fetch_eval(&session, sid, course).await {
+                Ok(eval) if dry_run => Outcome::Parsed(Box::new(eval)),
+                Ok(eval) => Outcome::Saved(save_eval(conn, eval).await?),
+                Err(e) if e.is::<SessionRejected>() => return Err(e),
+                Err(e) => Outcome::Failed(e),
+            };
+            let elapsed_ms = start.elapsed().as_millis() as u64;
 
-    for sid in &sids {
-        let start = Instant::now();
-        let res = get_eval(&client, *sid, course).await;
-        match res {
-            Ok(eval) => {
-                saved += query!(
-                    "
-                    INSERT INTO evaluations (
-                        sid, section_name, course_code, term_id, instructor_id,
-                        enrollment, responses,
-                        class_helped_understanding, assignments_helped_understanding, fair_exams,
-                        timely_feedback, developed_understanding, engaging, communication,
-                        help_opportunities, effective_methods, timeliness, welcoming, materials,
-                        hours, expected_grades, actual_grades
-                    )
-                    VALUES (
-                        $1, $2, $3, $4, $5,
-                        $6, $7,
-                        $8, $9, $10,
-                        $11, $12, $13, $14,
-                        $15, $16, $17, $18, $19,
-                        $20, $21, $22
-                    )
-                ",
-                    eval.sid,
-                    eval.section_name,
-                    eval.course_code,
-                    get_or_create_term_id(conn, eval.term).await?,
-                    get_or_create_instructor_id(conn, eval.instructor).await?,
-                    eval.enrollment,
-                    eval.responses,
-                    &eval.class_helped_understanding[..],
-                    &eval.assignments_helped_understanding[..],
-                    &eval.fair_exams[..],
-                    &eval.timely_feedback[..],
-                    &eval.developed_understanding[..],
-                    &eval.engaging[..],
-                    &eval.communication[..],
-                    &eval.help_opportunities[..],
-                    &eval.effective_methods[..],
-                    &eval.timeliness[..],
-                    &eval.welcoming[..],
-                    &eval.materials[..],
-                    &eval.hours[..],
-                    &eval.expected_grades[..],
-                    &eval.actual_grades[..],
-                )
-                .execute(conn)
-                .await?
-                .rows_affected();
+            if let Outcome::Failed(e) = &outcome {
+                warn!(elapsed_ms, kind = failure_kind(e), error = %format!("{e:#}"), "section failed");
+                failing += 1;
+                if failing >= RENEW_AFTER_FAILURES
+                    && !session.upstream().is_replaying()
+                    && !session.renewed_within(RENEW_INTERVAL)
+                {
+                    warn!(failing, "failures in a row, renewing session");
+                    if let Err(e) = session.renew().await {
+                        warn!(error = %e, "could not renew session");
+                    }
+                    failing = 0;
+                }
+            } else {
+                info!(elapsed_ms, "parsed section");
+                failing = 0;
             }
+            anyhow::Ok(outcome)
+        }
+        .instrument(span)
+        .await;
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
             Err(e) => {
-                failures.push((sid, e));
+                // Another session can still scrape it
+                queue.lock().unwrap().push_front((i, sid));
+                return Err(e);
             }
-        }
+        };
         pb.inc(1);
-        pb.println(format!("Parsed section {} in {:?}", sid, start.elapsed()));
-    }
 
-    if !failures.is_empty() {
-        pb.println(format!("{} failures for {}", failures.len(), course.name));
-        for failure in &failures {
-            pb.println(format!("{:?}", failure));
+        let finished = {
+            let mut progress = courses[i].lock().unwrap();
+            match outcome {
+                Outcome::Saved(n) => progress.saved += n,
+                Outcome::Parsed(eval) => progress.parsed.push(*eval),
+                Outcome::Failed(e) => progress.failures.push((sid, e)),
+            }
+            progress.remaining -= 1;
+            (progress.remaining == 0).then(|| {
+                (
+                    progress.saved,
+                    std::mem::take(&mut progress.parsed),
+                    std::mem::take(&mut progress.failures),
+                    // Dropping the last handle closes the span, which logs its timing
+                    std::mem::replace(&mut progress.span, Span::none()),
+                )
+            })
+        };
+        drop(course_span);
+        if let Some((saved, parsed, failures, span)) = finished {
+            failed.extend(
+                report(conn, course, saved, parsed, failures, pb, dry_run)
+                    .instrument(span)
+                    .await?,
+            );
+            if !dry_run {
+                session.save().await?;
+            }
         }
     }
 
-    pb.println(format!("Saved {} evaluations for {}", saved, course.name));
-
-    Ok(failures.is_empty())
+    if !dry_run {
+        session.save().await?;
+    }
+    Ok(failed)
 }
 
-async fn get_eval(client: &Client, sid: i32, course: &Course) -> Result<Evaluation> {
-    let html = get_eval_html(client, sid).await?;
-    let eval = parse(&html, sid, course)?;
+/// Fetches and parses a section, renewing the session and trying again if the site sends
+/// the login page. A replayed login page is returned as is, and a login page soon after
+/// the session was last renewed stops it with `SessionRejected`.
+async fn fetch_eval(session: &Session, sid: i32, course: &Course) -> Result<Evaluation> {
+    let res = get_eval(session.upstream(), sid, course).await;
+    if !res.as_ref().is_err_and(is_login_page) || session.upstream().is_replaying() {
+        return res;
+    }
+    if session.renewed_within(RENEW_INTERVAL) {
+        return Err(SessionRejected(session.name.clone()).into());
+    }
 
-    Ok(eval)
+    Span::current().record("renewed", true);
+    warn!("got the login page, renewing session");
+    if let Err(e) = session.renew().await {
+        warn!(error = %e, "could not renew session");
+        return res;
+    }
+    match get_eval(session.upstream(), sid, course).await {
+        Err(e) if is_login_page(&e) => Err(SessionRejected(session.name.clone()).into()),
+        res => res,
+    }
 }
 
-async fn get_eval_html(client: &Client, sid: i32) -> Result<Html> {
-    let url = format!(
-        "https://academicaffairs.ucsd.edu/Modules/Evals/SET/Reports/SETSummary.aspx?sid={sid}"
-    );
-    let res = client.get(url.clone()).send().await?;
-    let text = res.text().await?;
-
-    Ok(Html::parse_document(&text))
+fn is_login_page(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref(), Some(ParseError::LoginPage { .. }))
 }
 
-#[derive(Debug)]
-struct Evaluation {
-    sid: i32,
-    section_name: String,
-    course_code: String,
-    term: String,
-    instructor: String,
+async fn report(
+    conn: &Pool<Postgres>,
+    course: &Course,
+    saved: u64,
+    parsed: Vec<Evaluation>,
+    failures: Vec<(i32, anyhow::Error)>,
+    pb: &ProgressBar,
+    dry_run: bool,
+) -> Result<Vec<(i32, String)>> {
+    if !failures.is_empty() {
+        let mut kinds = BTreeMap::new();
+        for (_, e) in &failures {
+            *kinds.entry(failure_kind(e)).or_insert(0) += 1;
+        }
+        warn!(
+            failures = failures.len(),
+            kinds = %kinds
+                .iter()
+                .map(|(kind, count)| format!("{count} {kind}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            "failures for {}",
+            course.name
+        );
+    }
 
-    enrollment: i32,
-    responses: i32,
+    if dry_run {
+        let existing = query!(
+            "SELECT COUNT(*) FROM evaluations WHERE sid = ANY($1)",
+            &parsed.iter().map(|e| e.sid).collect::<Vec<_>>()[..]
+        )
+        .fetch_one(conn)
+        .await?
+        .count
+        .unwrap_or(0);
+        let summary = common::dry_run_summary(
+            &format!("evaluations for {}", course.name),
+            &parsed,
+            existing,
+        );
+        pb.suspend(|| println!("{summary}"));
+    } else {
+        info!(saved, "saved evaluations for {}", course.name);
+    }
 
-    class_helped_understanding: Vec<i32>,
-    assignments_helped_understanding: Vec<i32>,
-    fair_exams: Vec<i32>,
-    timely_feedback: Vec<i32>,
-    developed_understanding: Vec<i32>,
-    engaging: Vec<i32>,
-    communication: Vec<i32>,
-    help_opportunities: Vec<i32>,
-    effective_methods: Vec<i32>,
-    timeliness: Vec<i32>,
-    welcoming: Vec<i32>,
-    materials: Vec<i32>,
-    hours: Vec<i32>,
-    expected_grades: Vec<i32>,
-    actual_grades: Vec<i32>,
+    Ok(failures
+        .into_iter()
+        .map(|(sid, e)| (sid, format!("{e:#}")))
+        .collect())
 }
 
-fn parse(html: &Html, sid: i32, course: &Course) -> Result<Evaluation> {
-    let (instructor, (term, section_name)) = {
-        let selector =
-            Selector::parse("#ContentPlaceHolder1_EvalsContentPlaceHolder_lblSummaryTitle > p")
-                .unwrap();
-        let mut title = html.select(&selector);
-        let mut iter = title
-            .next()
-            .ok_or(anyhow!("Could not find title"))?
-            .children()
-            .filter_map(|child| child.value().as_text());
-        (
-            iter.next()
-                .map(|s| s.rmatch_indices(',').nth(1).map(|(i, _)| s.split_at(i + 2)))
-                .unwrap_or(None)
-                .ok_or(anyhow!("Could not find instructor name"))?
-                .1
-                .trim(),
-            {
-                let (first, second) = iter
-                    .next()
-                    .map(|s| s.split_once(","))
-                    .unwrap_or(None)
-                    .ok_or(anyhow!("Could not parse term"))?;
-                (
-                    first.trim(),
-                    Regex::new(r"Section ID .*? \((.*?)\)")?
-                        .captures(second)
-                        .map(|captures| captures.get(1))
-                        .unwrap_or(None)
-                        .map(|m| m.as_str())
-                        .ok_or(anyhow!("No matches for section ID"))?,
-                )
-            },
-        )
-    };
-
-    let (responses, enrollment) = {
-        let selector = Selector::parse(
-            "#ContentPlaceHolder1_EvalsContentPlaceHolder_lblSummaryTitle > p:nth-child(2)",
-        )
-        .unwrap();
-        let mut stats = html
-            .select(&selector)
-            .next()
-            .ok_or(anyhow!("Could not find stats"))?
-            .children()
-            .filter_map(|child| {
-                child
-                    .value()
-                    .as_text()
-                    .map(|text| text.split_once(": "))
-                    .unwrap_or(None)
-                    .map(|(_, s)| s.trim().parse::<i32>().ok())
-                    .unwrap_or(None)
-            });
-        (
-            stats
-                .next()
-                .ok_or(anyhow!("Could not find response count"))?,
-            stats
-                .next()
-                .ok_or(anyhow!("Could not find enrollment count"))?,
+async fn save_eval(conn: &Pool<Postgres>, eval: Evaluation) -> Result<u64> {
+    Ok(query!(
+        "
+        INSERT INTO evaluations (
+            sid, section_name, course_code, term_id, instructor_id,
+            enrollment, responses,
+            class_helped_understanding, assignments_helped_understanding, fair_exams,
+            timely_feedback, developed_understanding, engaging, communication,
+            help_opportunities, effective_methods, timeliness, welcoming, materials,
+            hours, expected_grades, actual_grades, quality_flags
         )
-    };
-
-    let expected_grades = parse_grades_table(
-        html,
-        Selector::parse(
-            "#ContentPlaceHolder1_EvalsContentPlaceHolder_tblExpectedGrades > tbody > tr",
+        VALUES (
+            $1, $2, $3, $4, $5,
+            $6, $7,
+            $8, $9, $10,
+            $11, $12, $13, $14,
+            $15, $16, $17, $18, $19,
+            $20, $21, $22, $23
         )
-        .unwrap(),
+        ON CONFLICT (sid) DO UPDATE
+        SET section_name = EXCLUDED.section_name,
+            course_code = EXCLUDED.course_code,
+            term_id = EXCLUDED.term_id,
+            instructor_id = EXCLUDED.instructor_id,
+            enrollment = EXCLUDED.enrollment,
+            responses = EXCLUDED.responses,
+            class_helped_understanding = EXCLUDED.class_helped_understanding,
+            assignments_helped_understanding = EXCLUDED.assignments_helped_understanding,
+            fair_exams = EXCLUDED.fair_exams,
+            timely_feedback = EXCLUDED.timely_feedback,
+            developed_understanding = EXCLUDED.developed_understanding,
+            engaging = EXCLUDED.engaging,
+            communication = EXCLUDED.communication,
+            help_opportunities = EXCLUDED.help_opportunities,
+            effective_methods = EXCLUDED.effective_methods,
+            timeliness = EXCLUDED.timeliness,
+            welcoming = EXCLUDED.welcoming,
+            materials = EXCLUDED.materials,
+            hours = EXCLUDED.hours,
+            expected_grades = EXCLUDED.expected_grades,
+            actual_grades = EXCLUDED.actual_grades,
+            quality_flags = EXCLUDED.quality_flags
+    ",
+        eval.sid,
+        eval.section_name,
+        eval.course_code,
+        get_or_create_term_id(conn, eval.term).await?,
+        get_or_create_instructor_id(conn, eval.instructor).await?,
+        eval.enrollment,
+        eval.responses,
+        &eval.class_helped_understanding[..],
+        &eval.assignments_helped_understanding[..],
+        &eval.fair_exams[..],
+        &eval.timely_feedback[..],
+        &eval.developed_understanding[..],
+        &eval.engaging[..],
+        &eval.communication[..],
+        &eval.help_opportunities[..],
+        &eval.effective_methods[..],
+        &eval.timeliness[..],
+        &eval.welcoming[..],
+        &eval.materials[..],
+        &eval.hours[..],
+        &eval.expected_grades[..],
+        &eval.actual_grades[..],
+        &eval
+            .quality_flags
+            .iter()
+            .map(|flag| flag.as_str().to_string())
+            .collect::<Vec<_>>()[..],
     )
-    .unwrap_or_default();
-
-    let actual_grades = parse_grades_table(
-        html,
-        Selector::parse(
-            "#ContentPlaceHolder1_EvalsContentPlaceHolder_tblGradesReceived > tbody > tr",
-        )
-        .unwrap(),
-    )?;
-
-    let (hours, materials, scales_range) = parse_hours_materials(html)?;
+    .execute(conn)
+    .await?
+    .rows_affected())
+}
 
-    let scales = scales_range
-        .map(|i| parse_scale::<6>(html, i))
-        .collect::<Result<Vec<_>>>()?;
+async fn get_eval(upstream: &Upstream, sid: i32, course: &Course) -> Result<Evaluation> {
+    let html = get_eval_html(upstream, sid).await?;
+    let eval = parse(&html, sid, &course.code)?;
 
-    Ok(Evaluation {
-        sid,
-        section_name: section_name.to_string(),
-        course_code: course.code.clone(),
-        term: term.to_string(),
-        instructor: instructor.to_string(),
-        enrollment,
-        responses,
-        class_helped_understanding: scales[0].clone(),
-        assignments_helped_understanding: scales[1].clone(),
-        fair_exams: scales[2].clone(),
-        timely_feedback: scales[3].clone(),
-        developed_understanding: scales[4].clone(),
-        engaging: scales[5].clone(),
-        communication: scales[6].clone(),
-        help_opportunities: scales[7].clone(),
-        effective_methods: scales[8].clone(),
-        timeliness: scales[9].clone(),
-        welcoming: scales[10].clone(),
-        materials,
-        hours,
-        expected_grades,
-        actual_grades,
-    })
+    Ok(eval)
 }
 
-fn parse_hours_materials(html: &Html) -> Result<(Vec<i32>, Vec<i32>, Range<u32>)> {
-    for long_hours_idx in 14..=20 {
-        if let Ok(hours) = parse_scale::<11>(html, long_hours_idx) {
-            return Ok((hours, parse_scale::<5>(html, long_hours_idx - 1)?, 0..11));
-        }
-    }
-    Ok((
-        parse_scale::<4>(html, 2)?,
-        parse_scale::<5>(html, 1)?,
-        4..15,
-    ))
+async fn get_eval_html(upstream: &Upstream, sid: i32) -> Result<Html> {
+    Ok(Html::parse_document(&get_eval_page(upstream, sid).await?))
 }
 
-fn parse_grades_table(html: &Html, selector: Selector) -> Result<Vec<i32>> {
-    let td_selector = Selector::parse("td").unwrap();
-
-    html.select(&selector)
-        .next()
-        .ok_or(anyhow!("Could not find expected grades table"))?
-        .select(&td_selector)
-        .map(|col| {
-            col.text()
-                .collect::<String>()
-                .trim()
-                .parse::<i32>()
-                .map_err(|err| anyhow!(err))
-        })
-        .collect::<Result<Vec<i32>>>()
+async fn get_eval_page(upstream: &Upstream, sid: i32) -> Result<String> {
+    upstream
+        .get(&format!(
+            "/Modules/Evals/SET/Reports/SETSummary.aspx?sid={sid}"
+        ))
+        .await
 }
 
-fn parse_scale<const N: usize>(html: &Html, index: u32) -> Result<Vec<i32>> {
-    let mut result = vec![0; N];
-    for (i, item) in result.iter_mut().enumerate() {
-        let selector = Selector::parse(&format!("#ContentPlaceHolder1_EvalsContentPlaceHolder_rptQuestionnaire_rptChoices_{index}_rbSelect_{i}"))
-            .map_err(|e| anyhow!("{e:?}"))?;
-        let text: String = html
-            .select(&selector)
-            .next()
-            .ok_or(anyhow!("Could not get scale {index} i={i}"))?
-            .text()
-            .take(1)
-            .collect();
-        *item = text.parse::<i32>()?;
+/// Saves a sanitized copy of a section's page to `<dir>/SETSummary/<sid>.html`, along with
+/// what the parser currently makes of it, for the golden tests and `mock-set`.
+pub async fn capture_fixture(conn: &Pool<Postgres>, sid: i32, dir: &Path) -> Result<()> {
+    let course_code = query!("SELECT course_code FROM sids WHERE sid = $1", sid)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(anyhow!("Unknown sid {sid}, run evals sids first"))?
+        .course_code;
+
+    let page = sanitize(&get_eval_page(&Upstream::new()?, sid).await?);
+    let result = parse(&Html::parse_document(&page), sid, &course_code);
+    if let Err(ParseError::LoginPage { .. }) = result {
+        bail!("Got the login page for {sid}, run reauth");
+    }
+    match &result {
+        Ok(_) => println!("Parsed {sid} ({course_code})"),
+        Err(e) => println!("Saving a page the parser rejects: {e}"),
     }
-    Ok(result)
+
+    let dir = dir.join("SETSummary");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(format!("{sid}.html")), page)?;
+    fs::write(
+        dir.join(format!("{sid}.json")),
+        serde_json::to_string_pretty(&Expected::new(result, &course_code))? + "\n",
+    )?;
+    println!(
+        "Saved {}, check the expected JSON before committing",
+        dir.join(format!("{sid}.html")).display()
+    );
+
+    Ok(())
 }
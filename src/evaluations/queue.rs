@@ -0,0 +1,218 @@
+use crate::database::{Course, Term};
+use anyhow::{bail, Result};
+use clap::Args;
+use sqlx::{query, query_as, Pool, Postgres};
+use tracing::info;
+
+/// Narrows the sections an `evals` command works on. Text filters are case-insensitive
+/// and accept `*` and `?` globs, e.g. `CSE 1*`.
+#[derive(Args, Debug, Default)]
+pub struct EvalFilter {
+    /// Course code or glob
+    pub course: Option<String>,
+    /// Unit id or name
+    #[arg(long)]
+    pub unit: Option<String>,
+    /// Subject code, e.g. CSE
+    #[arg(long)]
+    pub subject: Option<String>,
+    /// Term name or glob, e.g. "Fall 2023"
+    #[arg(long)]
+    pub term: Option<String>,
+    /// Maximum number of sections to process
+    #[arg(long)]
+    pub limit: Option<i64>,
+    /// Start with the courses most requested through the API, then the most recent terms
+    #[arg(long)]
+    pub by_demand: bool,
+}
+
+/// An [`EvalFilter`] that can also match the instructor of stored evaluations. The search
+/// results don't list instructors, so only `evals refresh` takes this.
+#[derive(Args, Debug, Default)]
+pub struct StoredFilter {
+    #[command(flatten)]
+    pub sections: EvalFilter,
+    /// Instructor name
+    #[arg(long)]
+    pub instructor: Option<String>,
+}
+
+impl EvalFilter {
+    fn course_pattern(&self) -> Option<String> {
+        self.course.as_deref().map(glob_to_like)
+    }
+
+    fn unit_pattern(&self) -> Option<String> {
+        self.unit.as_deref().map(glob_to_like)
+    }
+
+    fn subject_pattern(&self) -> Option<String> {
+        self.subject.as_deref().map(glob_to_like)
+    }
+
+    fn term_pattern(&self) -> Option<String> {
+        self.term.as_deref().map(glob_to_like)
+    }
+}
+
+impl StoredFilter {
+    fn instructor_pattern(&self) -> Option<String> {
+        self.instructor.as_deref().map(glob_to_like)
+    }
+}
+
+/// Converts a shell-style glob into a `LIKE` pattern.
+fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '\\' | '%' | '_' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            c => pattern.push(c),
+        }
+    }
+    pattern
+}
+
+/// Groups `(course, sid)` rows that are already ordered by course.
+pub(super) fn group_by_course(
+    rows: impl IntoIterator<Item = (Course, i32)>,
+) -> Vec<(Course, Vec<i32>)> {
+    let mut work: Vec<(Course, Vec<i32>)> = Vec::new();
+    for (course, sid) in rows {
+        match work.last_mut() {
+            Some((last, sids)) if last.code == course.code => sids.push(sid),
+            _ => work.push((course, vec![sid])),
+        }
+    }
+    work
+}
+
+/// Sections that have been found by `evals sids` but not scraped yet, leaving out any in
+/// quarantine. Sections found before terms were recorded have none, so recency goes by the
+/// sid, which the SET site assigns in order.
+pub async fn pending(
+    conn: &Pool<Postgres>,
+    filter: &EvalFilter,
+) -> Result<Vec<(Course, Vec<i32>)>> {
+    let rows = query!(
+        "
+            SELECT sids.sid, courses.code, courses.name, courses.unit_id
+            FROM sids
+            INNER JOIN courses ON sids.course_code = courses.code
+            INNER JOIN units ON courses.unit_id = units.id
+            LEFT JOIN course_accesses ON course_accesses.course_code = courses.code
+            WHERE sids.sid NOT IN (SELECT sid FROM evaluations)
+            AND sids.sid NOT IN (SELECT sid FROM evaluations_quarantine)
+            AND ($1::text IS NULL OR courses.code ILIKE $1)
+            AND ($2::text IS NULL OR units.id::text = $2 OR units.name ILIKE $2)
+            AND ($3::text IS NULL OR courses.subject ILIKE $3)
+            AND ($6::text IS NULL OR sids.term ILIKE $6)
+            ORDER BY
+                CASE WHEN $5 THEN COALESCE(course_accesses.num, 0) END DESC,
+                CASE WHEN $5 THEN MAX(sids.sid) OVER (PARTITION BY courses.code) END DESC,
+                courses.code,
+                CASE WHEN $5 THEN sids.sid END DESC,
+                sids.sid
+            LIMIT $4
+        ",
+        filter.course_pattern(),
+        filter.unit_pattern(),
+        filter.subject_pattern(),
+        filter.limit,
+        filter.by_demand,
+        filter.term_pattern(),
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(group_by_course(rows.into_iter().map(|row| {
+        (
+            Course {
+                code: row.code,
+                name: row.name,
+                unit_id: row.unit_id,
+            },
+            row.sid,
+        )
+    })))
+}
+
+/// Sections that already have an evaluation, for re-scraping.
+pub async fn stored(
+    conn: &Pool<Postgres>,
+    filter: &StoredFilter,
+) -> Result<Vec<(Course, Vec<i32>)>> {
+    if let Some(pattern) = filter.sections.term_pattern() {
+        let terms = query_as!(
+            Term,
+            "SELECT id, name FROM terms WHERE name ILIKE $1",
+            pattern
+        )
+        .fetch_all(conn)
+        .await?;
+        if terms.is_empty() {
+            bail!(
+                "No terms match {:?}",
+                filter.sections.term.as_deref().unwrap_or_default()
+            );
+        }
+        info!(
+            terms = terms
+                .into_iter()
+                .map(|t| t.name)
+                .collect::<Vec<_>>()
+                .join(", "),
+            "matching terms"
+        );
+    }
+
+    let rows = query!(
+        "
+            SELECT evaluations.sid, courses.code, courses.name, courses.unit_id
+            FROM evaluations
+            INNER JOIN courses ON evaluations.course_code = courses.code
+            INNER JOIN units ON courses.unit_id = units.id
+            INNER JOIN terms ON evaluations.term_id = terms.id
+            INNER JOIN instructors ON evaluations.instructor_id = instructors.id
+            LEFT JOIN course_accesses ON course_accesses.course_code = courses.code
+            WHERE ($1::text IS NULL OR courses.code ILIKE $1)
+            AND ($2::text IS NULL OR units.id::text = $2 OR units.name ILIKE $2)
+            AND ($3::text IS NULL OR courses.subject ILIKE $3)
+            AND ($4::text IS NULL OR terms.name ILIKE $4)
+            AND ($5::text IS NULL OR instructors.name ILIKE $5)
+            ORDER BY
+                CASE WHEN $7 THEN COALESCE(course_accesses.num, 0) END DESC,
+                CASE WHEN $7 THEN MAX(term_recency(terms.name)) OVER (PARTITION BY courses.code) END DESC,
+                courses.code,
+                CASE WHEN $7 THEN term_recency(terms.name) END DESC,
+                evaluations.sid
+            LIMIT $6
+        ",
+        filter.sections.course_pattern(),
+        filter.sections.unit_pattern(),
+        filter.sections.subject_pattern(),
+        filter.sections.term_pattern(),
+        filter.instructor_pattern(),
+        filter.sections.limit,
+        filter.sections.by_demand,
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(group_by_course(rows.into_iter().map(|row| {
+        (
+            Course {
+                code: row.code,
+                name: row.name,
+                unit_id: row.unit_id,
+            },
+            row.sid,
+        )
+    })))
+}
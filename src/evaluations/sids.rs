@@ -1,46 +1,48 @@
 use crate::common;
 use crate::database::{Course, SectionId};
-use anyhow::{anyhow, Result};
+use crate::sessions::Session;
+use crate::upstream::{StatusError, Upstream};
+use crate::windows;
+use anyhow::{anyhow, bail, Result};
 use futures::{stream, StreamExt};
 use regex::Regex;
-use reqwest::Client;
+use scraper::{Html, Selector};
 use sqlx::{query, query_as, Pool, Postgres};
 use tokio::time::Instant;
+use tracing::{info, info_span, instrument, warn, Instrument};
 
-pub async fn save_all_sids(conn: &Pool<Postgres>) -> Result<()> {
+/// Passes over the failed searches before the courses still failing are given up on.
+const RETRY_PASSES: u32 = 5;
+
+#[instrument(name = "run", skip_all, fields(stage = "sids", dry_run))]
+pub async fn save_all_sids(conn: &Pool<Postgres>, session: &Session, dry_run: bool) -> Result<()> {
     let courses = query_as!(Course, "SELECT code, name, unit_id FROM courses")
         .fetch_all(conn)
         .await?;
 
-    let client = common::client()?;
+    let upstream = session.upstream();
 
     let pb = common::progress_bar(courses.len() as u64);
-    let results = stream::iter(&courses)
-        .map(|course| {
-            let client = &client;
+    // Indexed like the units in `get_all_courses`
+    let results = stream::iter(0..courses.len())
+        .map(|i| {
+            let course = &courses[i];
             let pb = &pb;
             async move {
+                windows::wait_for_open().await;
                 let start = Instant::now();
-                let res = get_sids(client, course).await;
+                let res = get_sids(upstream, course).await;
+                let elapsed_ms = start.elapsed().as_millis() as u64;
                 match &res {
-                    Ok(sids) => {
-                        pb.println(format!(
-                            "[+] found {} SIDs for {} in {:?}",
-                            sids.len(),
-                            course.name,
-                            start.elapsed()
-                        ));
-                    }
-                    Err(_) => {
-                        pb.println(format!(
-                            "[-] error in {}: adding to retry queue",
-                            course.name
-                        ));
+                    Ok(sids) => info!(elapsed_ms, sids = sids.len(), "found sids"),
+                    Err(e) => {
+                        warn!(elapsed_ms, error = %format!("{e:#}"), "search failed")
                     }
                 };
                 pb.inc(1);
                 (course, res)
             }
+            .instrument(info_span!("course", code = %course.code))
         })
         .buffer_unordered(20)
         .collect::<Vec<_>>()
@@ -51,76 +53,125 @@ pub async fn save_all_sids(conn: &Pool<Postgres>) -> Result<()> {
     let (sids, errors): (Vec<_>, Vec<_>) = results.into_iter().partition(|(_, res)| res.is_ok());
     let mut sids = sids
         .into_iter()
-        .flat_map(|(course, res)| res.unwrap().into_iter().map(move |sid| (course, sid)))
+        .flat_map(|(course, res)| {
+            res.unwrap()
+                .into_iter()
+                .map(move |(sid, term)| (course, sid, term))
+        })
         .collect::<Vec<_>>();
-    let errors = errors
+    let mut problems = errors
         .into_iter()
-        .map(|(course, _)| course)
+        .map(|(course, res)| (course, res.unwrap_err()))
         .collect::<Vec<_>>();
 
-    println!("Found {} SIDs with {} errors", sids.len(), errors.len());
-
-    if !errors.is_empty() {
-        let mut problems = errors;
+    info!(sids = sids.len(), errors = problems.len(), "found sids");
 
+    if !problems.is_empty() {
         let pb = common::progress_bar(problems.len() as u64);
 
-        while !problems.is_empty() {
-            // try to fix them
-            let mut fixed = Vec::new();
-            for (i, &course) in problems.iter().enumerate() {
-                if let Ok(results) = get_sids(&client, course).await {
-                    fixed.push(i);
-                    sids.extend(results.into_iter().map(|sid| (course, sid)));
-                    pb.println(format!("[+] fixed {}", course.name));
-                    pb.inc(1);
-                }
+        // Only network errors and error statuses can go away by asking again
+        for _ in 0..RETRY_PASSES {
+            if !problems.iter().any(|(_, e)| is_transient(e)) {
+                break;
             }
-            for i in fixed.into_iter().rev() {
-                problems.remove(i);
+            let mut left = Vec::new();
+            for (course, e) in problems {
+                if !is_transient(&e) {
+                    left.push((course, e));
+                    continue;
+                }
+                windows::wait_for_open().await;
+                match get_sids(upstream, course).await {
+                    Ok(results) => {
+                        sids.extend(results.into_iter().map(|(sid, term)| (course, sid, term)));
+                        info!(code = %course.code, "found sids on retry");
+                        pb.inc(1);
+                    }
+                    Err(e) => left.push((course, e)),
+                }
             }
+            problems = left;
         }
         pb.finish();
-        println!("Reduced to {} errors", problems.len());
+        for (course, e) in &problems {
+            warn!(code = %course.code, error = %format!("{e:#}"), "could not search course");
+        }
+        info!(
+            sids = sids.len(),
+            errors = problems.len(),
+            "retried failed searches"
+        );
+    }
 
-        println!("Now at {} SIDs", sids.len());
+    if !dry_run {
+        session.save().await?;
     }
 
     let values = sids
         .clone()
         .into_iter()
-        .map(|(course, sid)| SectionId {
+        .map(|(course, sid, term)| SectionId {
             sid,
             course_code: course.code.clone(),
+            term: Some(term),
         })
         .collect::<Vec<_>>();
 
+    if dry_run {
+        let existing = query!(
+            "SELECT COUNT(*) FROM sids WHERE sid = ANY($1)",
+            &values.iter().map(|s| s.sid).collect::<Vec<_>>()[..]
+        )
+        .fetch_one(conn)
+        .await?
+        .count
+        .unwrap_or(0);
+        println!("{}", common::dry_run_summary("SIDs", &values, existing));
+        return unsearched(&problems);
+    }
+
     let saved = query!(
         "
-            INSERT INTO sids (sid, course_code)
-            SELECT * FROM UNNEST($1::int[], $2::text[])
-            ON CONFLICT DO NOTHING
+            INSERT INTO sids (sid, course_code, term)
+            SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[])
+            ON CONFLICT (sid) DO UPDATE
+            SET term = EXCLUDED.term
+            WHERE sids.term IS DISTINCT FROM EXCLUDED.term
         ",
         &values.iter().map(|s| s.sid).collect::<Vec<_>>()[..],
         &values
-            .into_iter()
-            .map(|s| s.course_code)
-            .collect::<Vec<_>>()[..]
+            .iter()
+            .map(|s| s.course_code.clone())
+            .collect::<Vec<_>>()[..],
+        &values.into_iter().map(|s| s.term).collect::<Vec<_>>()[..] as &[Option<String>]
     )
     .execute(conn)
     .await?
     .rows_affected();
 
-    println!("{saved} SIDs saved");
+    info!(saved, "saved sids");
 
+    unsearched(&problems)
+}
+
+/// Fails the run if any course is still missing its sids, after what was found is saved.
+fn unsearched(problems: &[(&Course, anyhow::Error)]) -> Result<()> {
+    if !problems.is_empty() {
+        bail!("Could not search {} courses", problems.len());
+    }
     Ok(())
 }
 
-async fn get_sids(client: &Client, course: &Course) -> Result<Vec<i32>> {
-    let res = client
-        .post("https://academicaffairs.ucsd.edu/Modules/Evals/SET/Reports/Search.aspx")
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&[
+/// Whether a failed search might work if tried again.
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>().is_some() || e.downcast_ref::<StatusError>().is_some()
+}
+
+async fn get_sids(upstream: &Upstream, course: &Course) -> Result<Vec<(i32, String)>> {
+    let text = upstream
+        .post_form(
+            "/Modules/Evals/SET/Reports/Search.aspx",
+            &[
             ("__EVENTTARGET", "".to_string()),
             ("ctl00$ctl00$ContentPlaceHolder1$EvalsContentPlaceHolder$ddlUnit", course.unit_id.to_string()),
             (
@@ -128,18 +179,57 @@ async fn get_sids(client: &Client, course: &Course) -> Result<Vec<i32>> {
                 format!("{}:::{}", course.code, course.name.replace(" ", "+"))
             ),
             ("ctl00$ctl00$ContentPlaceHolder1$EvalsContentPlaceHolder$btnSubmit", "Search".to_string())
-        ]).send().await?;
-    let text = res.text().await?;
-
-    let re = Regex::new(r#"window\.open\('SETSummary\.aspx\?sid=([0-9]*?)',"#)?;
-    let res = re
-        .captures_iter(&text)
-        .map(|c| {
-            c.get(1)
-                .ok_or(anyhow!("Match had no groups"))
-                .map(|m| m.as_str().parse::<i32>().unwrap())
+            ],
+        )
+        .await?;
+
+    parse_sids(&text)
+}
+
+/// Reads the sid and term of every section in a page of search results.
+fn parse_sids(text: &str) -> Result<Vec<(i32, String)>> {
+    let html = Html::parse_document(text);
+    let row = Selector::parse("tr").unwrap();
+    let cell = Selector::parse("td").unwrap();
+    let link = Regex::new(r#"window\.open\('SETSummary\.aspx\?sid=([0-9]*?)',"#)?;
+    html.select(&row)
+        .filter_map(|row| {
+            let sid = link
+                .captures(&row.inner_html())?
+                .get(1)?
+                .as_str()
+                .to_string();
+            Some((row, sid))
         })
-        .collect::<Result<Vec<_>>>();
+        .map(|(row, sid)| {
+            let term = row
+                .select(&cell)
+                .next()
+                .ok_or(anyhow!("Search result for {sid} has no cells"))?
+                .text()
+                .collect::<String>()
+                .trim()
+                .to_string();
+            Ok((sid.parse()?, term))
+        })
+        .collect()
+}
 
-    res
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn reads_terms_from_search_results() {
+        let text =
+            std::fs::read_to_string(testing::fixtures().join("Search/CSE_120.html")).unwrap();
+        assert_eq!(
+            parse_sids(&text).unwrap(),
+            [
+                (100001, "Fall 2023".to_string()),
+                (100002, "Winter 2024".to_string()),
+            ]
+        );
+    }
 }
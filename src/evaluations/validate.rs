@@ -0,0 +1,355 @@
+use anyhow::Result;
+use sqlx::{query, query_as, Pool, Postgres};
+use std::collections::BTreeMap;
+
+/// How many example sections the report lists for each kind of problem.
+const EXAMPLES: usize = 5;
+
+/// An evaluation as stored, with its term and instructor looked up.
+pub struct StoredEvaluation {
+    pub sid: i32,
+    pub term: String,
+    pub recency: Option<i32>,
+    pub instructor: String,
+    pub enrollment: i32,
+    pub responses: i32,
+    pub class_helped_understanding: Vec<i32>,
+    pub assignments_helped_understanding: Vec<i32>,
+    pub fair_exams: Vec<i32>,
+    pub timely_feedback: Vec<i32>,
+    pub developed_understanding: Vec<i32>,
+    pub engaging: Vec<i32>,
+    pub communication: Vec<i32>,
+    pub help_opportunities: Vec<i32>,
+    pub effective_methods: Vec<i32>,
+    pub timeliness: Vec<i32>,
+    pub welcoming: Vec<i32>,
+    pub materials: Vec<i32>,
+    pub hours: Vec<i32>,
+    pub expected_grades: Vec<i32>,
+    pub actual_grades: Vec<i32>,
+}
+
+pub struct Problem {
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+fn problem(kind: &'static str, detail: String) -> Problem {
+    Problem { kind, detail }
+}
+
+/// Every invariant `eval` breaks. Parsing fills in defaults in places, so a row that
+/// saved fine can still be wrong.
+pub fn problems(eval: &StoredEvaluation) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if eval.responses < 0 || eval.responses > eval.enrollment {
+        problems.push(problem(
+            "responses",
+            format!(
+                "{} responses from {} enrolled",
+                eval.responses, eval.enrollment
+            ),
+        ));
+    }
+
+    let scales = [
+        (
+            "class_helped_understanding",
+            &eval.class_helped_understanding,
+        ),
+        (
+            "assignments_helped_understanding",
+            &eval.assignments_helped_understanding,
+        ),
+        ("fair_exams", &eval.fair_exams),
+        ("timely_feedback", &eval.timely_feedback),
+        ("developed_understanding", &eval.developed_understanding),
+        ("engaging", &eval.engaging),
+        ("communication", &eval.communication),
+        ("help_opportunities", &eval.help_opportunities),
+        ("effective_methods", &eval.effective_methods),
+        ("timeliness", &eval.timeliness),
+        ("welcoming", &eval.welcoming),
+    ];
+    for (name, counts) in scales {
+        check_question(&mut problems, name, counts, 6, eval.responses);
+    }
+    check_question(
+        &mut problems,
+        "materials",
+        &eval.materials,
+        5,
+        eval.responses,
+    );
+    let hours_len = if eval.hours.len() == 11 { 11 } else { 4 };
+    check_question(
+        &mut problems,
+        "hours",
+        &eval.hours,
+        hours_len,
+        eval.responses,
+    );
+
+    if eval.actual_grades.is_empty() {
+        problems.push(problem("grades", "no actual grades".to_string()));
+    }
+    if !eval.expected_grades.is_empty() {
+        if eval.expected_grades.len() != eval.actual_grades.len() {
+            problems.push(problem(
+                "grades",
+                format!(
+                    "{} expected grade buckets but {} actual",
+                    eval.expected_grades.len(),
+                    eval.actual_grades.len()
+                ),
+            ));
+        }
+        check_counts(
+            &mut problems,
+            "expected_grades",
+            &eval.expected_grades,
+            eval.responses,
+        );
+    }
+    if eval.actual_grades.iter().any(|&n| n < 0) {
+        problems.push(problem(
+            "negative count",
+            "actual_grades has a negative count".to_string(),
+        ));
+    }
+
+    // term_recency gives the year times ten plus the quarter, with zeros for parts it
+    // can't find
+    if eval
+        .recency
+        .is_none_or(|recency| recency < 10 || recency % 10 == 0)
+    {
+        problems.push(problem(
+            "term",
+            format!("unrecognized term {:?}", eval.term),
+        ));
+    }
+    if !eval.instructor.chars().any(char::is_alphabetic) {
+        problems.push(problem(
+            "instructor",
+            format!("unlikely instructor name {:?}", eval.instructor),
+        ));
+    }
+
+    problems
+}
+
+fn check_question(
+    problems: &mut Vec<Problem>,
+    name: &str,
+    counts: &[i32],
+    len: usize,
+    responses: i32,
+) {
+    if counts.len() != len {
+        problems.push(problem(
+            "question length",
+            format!("{name} has {} buckets, expected {len}", counts.len()),
+        ));
+    }
+    check_counts(problems, name, counts, responses);
+}
+
+/// Answer counts can't be negative or add up to more than the number of responses.
+fn check_counts(problems: &mut Vec<Problem>, name: &str, counts: &[i32], responses: i32) {
+    if counts.iter().any(|&n| n < 0) {
+        problems.push(problem(
+            "negative count",
+            format!("{name} has a negative count"),
+        ));
+    }
+    let total = counts.iter().map(|&n| n as i64).sum::<i64>();
+    if total > responses as i64 {
+        problems.push(problem(
+            "histogram total",
+            format!("{name} adds up to {total} with {responses} responses"),
+        ));
+    }
+}
+
+/// Checks every stored evaluation and prints a report. With `quarantine`, rows with
+/// problems are moved to `evaluations_quarantine`, out of the API and the scrape queue.
+pub async fn validate(conn: &Pool<Postgres>, quarantine: bool) -> Result<()> {
+    let evals = query_as!(
+        StoredEvaluation,
+        r#"
+            SELECT
+                evaluations.sid, terms.name AS term, term_recency(terms.name) AS recency,
+                instructors.name AS instructor, enrollment, responses,
+                class_helped_understanding, assignments_helped_understanding, fair_exams,
+                timely_feedback, developed_understanding, engaging, communication,
+                help_opportunities, effective_methods, timeliness, welcoming, materials,
+                hours, expected_grades, actual_grades
+            FROM evaluations
+            INNER JOIN terms ON evaluations.term_id = terms.id
+            INNER JOIN instructors ON evaluations.instructor_id = instructors.id
+            ORDER BY evaluations.sid
+        "#
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut bad = Vec::new();
+    let mut by_kind: BTreeMap<&str, Vec<(i32, String)>> = BTreeMap::new();
+    for eval in &evals {
+        let problems = problems(eval);
+        if problems.is_empty() {
+            continue;
+        }
+        bad.push((
+            eval.sid,
+            problems
+                .iter()
+                .map(|p| p.detail.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+        ));
+        for p in problems {
+            by_kind
+                .entry(p.kind)
+                .or_default()
+                .push((eval.sid, p.detail));
+        }
+    }
+
+    println!("{} of {} evaluations have problems", bad.len(), evals.len());
+    for (kind, found) in &by_kind {
+        println!("{kind}: {}", found.len());
+        for (sid, detail) in found.iter().take(EXAMPLES) {
+            println!("  {sid}: {detail}");
+        }
+    }
+
+    if quarantine && !bad.is_empty() {
+        let moved = query!(
+            "
+                WITH moved AS (
+                    DELETE FROM evaluations WHERE sid = ANY($1) RETURNING *
+                )
+                INSERT INTO evaluations_quarantine (
+                    sid, section_name, course_code, term_id, instructor_id,
+                    enrollment, responses,
+                    class_helped_understanding, assignments_helped_understanding, fair_exams,
+                    timely_feedback, developed_understanding, engaging, communication,
+                    help_opportunities, effective_methods, timeliness, welcoming, materials,
+                    hours, expected_grades, actual_grades, quality_flags, problems
+                )
+                SELECT
+                    moved.sid, section_name, course_code, term_id, instructor_id,
+                    enrollment, responses,
+                    class_helped_understanding, assignments_helped_understanding, fair_exams,
+                    timely_feedback, developed_understanding, engaging, communication,
+                    help_opportunities, effective_methods, timeliness, welcoming, materials,
+                    hours, expected_grades, actual_grades, quality_flags, bad.problems
+                FROM moved
+                INNER JOIN UNNEST($1::int[], $2::text[]) AS bad (sid, problems)
+                ON bad.sid = moved.sid
+            ",
+            &bad.iter().map(|(sid, _)| *sid).collect::<Vec<_>>()[..],
+            &bad.into_iter()
+                .map(|(_, problems)| problems)
+                .collect::<Vec<_>>()[..]
+        )
+        .execute(conn)
+        .await?
+        .rows_affected();
+        println!("Quarantined {moved} evaluations");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval() -> StoredEvaluation {
+        let scale = vec![1, 2, 3, 4, 0, 0];
+        StoredEvaluation {
+            sid: 1,
+            term: "Fall 2023".to_string(),
+            recency: Some(20234),
+            instructor: "Doe, Jane".to_string(),
+            enrollment: 30,
+            responses: 10,
+            class_helped_understanding: scale.clone(),
+            assignments_helped_understanding: scale.clone(),
+            fair_exams: scale.clone(),
+            timely_feedback: scale.clone(),
+            developed_understanding: scale.clone(),
+            engaging: scale.clone(),
+            communication: scale.clone(),
+            help_opportunities: scale.clone(),
+            effective_methods: scale.clone(),
+            timeliness: scale.clone(),
+            welcoming: scale,
+            materials: vec![2, 2, 2, 2, 2],
+            hours: vec![4, 3, 2, 1],
+            expected_grades: vec![5, 3, 1, 0, 0, 1, 0],
+            actual_grades: vec![12, 10, 5, 1, 1, 1, 0],
+        }
+    }
+
+    fn kinds(eval: &StoredEvaluation) -> Vec<&'static str> {
+        problems(eval).into_iter().map(|p| p.kind).collect()
+    }
+
+    #[test]
+    fn valid_evaluation_has_no_problems() {
+        assert!(kinds(&eval()).is_empty());
+        assert!(kinds(&StoredEvaluation {
+            expected_grades: Vec::new(),
+            hours: vec![1; 10].into_iter().chain([0]).collect(),
+            ..eval()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn finds_broken_invariants() {
+        assert_eq!(
+            kinds(&StoredEvaluation {
+                responses: 40,
+                ..eval()
+            }),
+            ["responses"]
+        );
+        assert_eq!(
+            kinds(&StoredEvaluation {
+                fair_exams: vec![9, 9, 0, 0, 0, 0],
+                ..eval()
+            }),
+            ["histogram total"]
+        );
+        assert_eq!(
+            kinds(&StoredEvaluation {
+                materials: vec![1, 1],
+                ..eval()
+            }),
+            ["question length"]
+        );
+        assert_eq!(
+            kinds(&StoredEvaluation {
+                actual_grades: Vec::new(),
+                ..eval()
+            }),
+            ["grades", "grades"]
+        );
+        assert_eq!(
+            kinds(&StoredEvaluation {
+                term: "2023".to_string(),
+                recency: Some(20230),
+                instructor: ", ".to_string(),
+                ..eval()
+            }),
+            ["term", "instructor"]
+        );
+    }
+}
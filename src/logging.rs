@@ -0,0 +1,87 @@
+use anyhow::Result;
+use indicatif::ProgressBar;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::RwLock;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+/// The progress bar on screen, which terminal log lines are printed above.
+static BAR: RwLock<Option<ProgressBar>> = RwLock::new(None);
+
+/// Prints log lines above `pb` until another bar replaces it.
+pub fn set_progress_bar(pb: &ProgressBar) {
+    *BAR.write().unwrap() = Some(pb.clone());
+}
+
+/// Sends log output to the terminal and, with `log_file`, appends it as JSON lines with
+/// span timings. `RUST_LOG` overrides the default `info` level. Keep the guard until
+/// exit so the file gets everything.
+pub fn init(log_file: Option<&Path>) -> Result<Option<WorkerGuard>> {
+    let filter = || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let terminal = fmt::layer()
+        .without_time()
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .with_writer(Terminal)
+        .with_filter(filter());
+
+    let (file, guard) = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            let layer = fmt::layer()
+                .json()
+                .with_span_list(true)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(writer)
+                .with_filter(filter());
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(terminal)
+        .with(file)
+        .try_init()?;
+    Ok(guard)
+}
+
+/// Writes each log line above the progress bar if one is being drawn, or to stderr.
+struct Terminal;
+
+struct Line(Vec<u8>);
+
+impl<'a> MakeWriter<'a> for Terminal {
+    type Writer = Line;
+
+    fn make_writer(&'a self) -> Line {
+        Line(Vec::new())
+    }
+}
+
+impl Write for Line {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Line {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.0);
+        match &*BAR.read().unwrap() {
+            Some(pb) if !pb.is_hidden() && !pb.is_finished() => pb.println(line.trim_end()),
+            _ => eprint!("{line}"),
+        }
+    }
+}
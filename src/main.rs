@@ -1,29 +1,51 @@
 mod api;
+mod auth;
 mod common;
 mod cookies;
 mod courses;
 mod database;
 mod evaluations;
+mod logging;
+mod scheduler;
+mod sessions;
+#[cfg(test)]
+mod testing;
+mod upstream;
+mod windows;
 
-use crate::common::progress_bar;
-use crate::courses::get_all_courses;
+use crate::auth::renewal::{self, SessionHealth};
+use crate::cookies::SessionCookies;
+use crate::courses::{get_all_courses, get_units};
 use crate::database::establish_connection;
-use crate::evaluations::save_evals;
+use crate::evaluations::jobs::{self, WorkerArgs};
+use crate::evaluations::queue::{self, EvalFilter, StoredFilter};
 use crate::evaluations::sids::save_all_sids;
-use anyhow::Result;
+use crate::evaluations::validate;
+use crate::evaluations::{capture_fixture, save_evals};
+use crate::scheduler::ScheduleConfig;
+use crate::sessions::{Session, SessionConfig};
+use crate::upstream::{ProxyConfig, ProxyStrategy, Upstream, UpstreamArgs};
+use crate::windows::Window;
+use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
 use config::Config;
 use courses::display_stats;
-use database::Course;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
 use serde::Deserialize;
-use sqlx::{query, query_as};
+use sqlx::query;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::OnceCell;
+use tracing::info;
 
 static SETTINGS: OnceCell<Settings> = OnceCell::const_new();
 
 pub(crate) fn settings() -> &'static Settings {
+    #[cfg(test)]
+    if SETTINGS.get().is_none() {
+        let _ = SETTINGS.set(testing::settings());
+    }
     SETTINGS.get().unwrap()
 }
 
@@ -32,8 +54,22 @@ struct Settings {
     service_url: String,
     proxy_username: Option<String>,
     proxy_password: Option<String>,
+    proxies: Option<Vec<ProxyConfig>>,
+    proxy_strategy: Option<ProxyStrategy>,
+    upstream_url: Option<String>,
+    cache_dir: Option<String>,
     cookies_token: String,
     database_url: String,
+    cookie_store: Option<String>,
+    cookies_file: Option<String>,
+    auth_provider: Option<String>,
+    auth_file: Option<String>,
+    auth_command: Option<String>,
+    requests_per_minute: Option<u32>,
+    sessions: Option<Vec<SessionConfig>>,
+    schedule: Option<Vec<ScheduleConfig>>,
+    scrape_windows: Option<Vec<Window>>,
+    log_file: Option<String>,
 }
 
 #[derive(Parser)]
@@ -41,10 +77,42 @@ struct Settings {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    upstream: UpstreamArgs,
+    /// Also write logs to this file as JSON lines, with span timings
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    #[command(flatten)]
+    Database(DatabaseCommands),
+    #[command(flatten)]
+    Mock(MockCommands),
+}
+
+/// Stand-in servers for working offline, which don't need the database.
+#[derive(Subcommand)]
+enum MockCommands {
+    /// Run a stand-in cookie server for testing reauth locally
+    MockAuth {
+        host: Option<String>,
+        /// Serve the cookies in this export instead of made-up sessions
+        #[arg(long)]
+        cookies: Option<PathBuf>,
+    },
+    /// Run a stand-in SET site that serves fixture pages
+    MockSet {
+        host: Option<String>,
+        #[arg(long, default_value = "tests/fixtures")]
+        fixtures: PathBuf,
+    },
+}
+
+/// Commands that work on the database.
+#[derive(Subcommand)]
+enum DatabaseCommands {
     Courses {
         #[command(subcommand)]
         command: CourseCommands,
@@ -53,34 +121,166 @@ enum Commands {
         #[command(subcommand)]
         command: EvalCommands,
     },
-    Reauth,
+    Reauth {
+        /// Only check whether the current session still works
+        #[arg(long, conflicts_with = "from_file")]
+        check: bool,
+        /// Import cookies from a Netscape cookies.txt, a browser extension's JSON export or
+        /// a raw Cookie header instead of asking the cookie server
+        #[arg(long)]
+        from_file: Option<PathBuf>,
+        /// Act on the named session from [[sessions]] instead of the default one
+        #[arg(long)]
+        session: Option<String>,
+    },
     Serve {
         host: Option<String>,
+        /// Only serve the API, ignoring [[schedule]]
+        #[arg(long)]
+        no_schedule: bool,
+    },
+    /// Scrape sections from the scrape_jobs queue alongside any other workers
+    Worker {
+        #[command(flatten)]
+        args: WorkerArgs,
     },
 }
 
 #[derive(Subcommand)]
 enum CourseCommands {
     Stats,
-    Fetch,
+    Fetch {
+        /// Exit successfully even if some units could not be fetched. Courses from the
+        /// units that were fetched are saved either way
+        #[arg(long)]
+        allow_partial: bool,
+        /// Fetch and parse everything but only print what would be saved
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum EvalCommands {
     Stats,
-    Fetch { course: Option<String> },
-    Sids,
+    /// Scrape sections that have no evaluation yet
+    Fetch {
+        #[command(flatten)]
+        filter: EvalFilter,
+        /// Fetch and parse evaluations but only print what would be saved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Re-scrape sections that already have an evaluation
+    Refresh {
+        #[command(flatten)]
+        filter: StoredFilter,
+    },
+    /// Add sections to the scrape_jobs queue for workers
+    Enqueue {
+        #[command(flatten)]
+        filter: StoredFilter,
+        /// Queue sections that already have an evaluation instead of pending ones
+        #[arg(long)]
+        refresh: bool,
+        /// Workers take higher priorities first
+        #[arg(long, default_value_t = 0)]
+        priority: i32,
+    },
+    Sids {
+        /// Search for sections but only print what would be saved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check stored evaluations for impossible values
+    Validate {
+        /// Move evaluations with problems to the evaluations_quarantine table
+        #[arg(long)]
+        quarantine: bool,
+    },
+    /// Save a section's page as a parser test fixture
+    CaptureFixture {
+        sid: i32,
+        #[arg(long, default_value = "tests/fixtures")]
+        fixtures: PathBuf,
+    },
 }
 
-async fn reauth() -> Result<()> {
+async fn reauth(session: &Session) -> Result<()> {
     let pb = ProgressBar::new_spinner();
     pb.enable_steady_tick(Duration::from_millis(80));
     pb.set_style(ProgressStyle::with_template("{spinner:.blue} {msg}")?);
     pb.set_message("Fetching new cookies");
 
-    cookies::fetch_cookies(&settings().cookies_token).await?;
+    session.renew().await?;
 
-    pb.finish_with_message("Done");
+    match renewal::expires_in(session.cookies()) {
+        Some(left) => {
+            pb.finish_with_message(format!("Done, cookies expire in {}m", left.as_secs() / 60))
+        }
+        None => pb.finish_with_message("Done"),
+    }
+
+    Ok(())
+}
+
+async fn check_session(session: &Session) -> Result<()> {
+    match renewal::check(session).await? {
+        SessionHealth::Alive {
+            expires_in: Some(left),
+        } => println!(
+            "Session is alive, cookies expire in {}m",
+            left.as_secs() / 60
+        ),
+        SessionHealth::Alive { expires_in: None } => {
+            println!("Session is alive, cookies carry no expiry")
+        }
+        SessionHealth::Expired => bail!("Session has expired, run reauth"),
+    }
+
+    Ok(())
+}
+
+async fn import_cookies(session: &Session, path: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let cookies = cookies::import::parse_export(&contents)?;
+    if cookies.is_empty() {
+        bail!("No academicaffairs.ucsd.edu cookies in {}", path.display());
+    }
+    println!("Found {} cookies", cookies.len());
+
+    let imported = Arc::new(SessionCookies::new(cookies));
+    let upstream = Upstream::with_cookies(imported.clone())?.uncached();
+    let units = get_units(&upstream)
+        .await
+        .context("The imported cookies were rejected by the SET site")?;
+    if units.is_empty() {
+        bail!("The imported cookies were rejected by the SET site");
+    }
+
+    // Keeps anything the SET site refreshed while checking them
+    session.replace(imported.get()).await?;
+    println!("Session is valid, saved cookies");
+
+    Ok(())
+}
+
+async fn serve_mock(command: MockCommands) -> Result<()> {
+    let (app, host, name) = match command {
+        MockCommands::MockAuth { host, cookies } => (
+            auth::mock::app(&settings().cookies_token, cookies.as_deref())?,
+            host.unwrap_or("127.0.0.1:5001".to_string()),
+            "cookie server",
+        ),
+        MockCommands::MockSet { host, fixtures } => (
+            upstream::mock::app(fixtures),
+            host.unwrap_or("127.0.0.1:5002".to_string()),
+            "SET site",
+        ),
+    };
+    let listener = tokio::net::TcpListener::bind(&host).await?;
+    println!("Mock {name} listening on {host}");
+    axum::serve(listener, app.into_make_service()).await?;
 
     Ok(())
 }
@@ -95,76 +295,96 @@ async fn main() -> Result<()> {
         SETTINGS.set(settings.try_deserialize::<Settings>()?)?;
     }
 
-    let conn = establish_connection().await?;
-
     let cli = Cli::parse();
+    let _log_guard = logging::init(
+        cli.log_file
+            .as_deref()
+            .or(settings().log_file.as_deref().map(Path::new)),
+    )?;
+    upstream::init(cli.upstream.clone())?;
 
-    match cli.command {
-        Commands::Reauth => {
-            reauth().await?;
+    let command = match cli.command {
+        Commands::Mock(command) => return serve_mock(command).await,
+        Commands::Database(command) => command,
+    };
+
+    let conn = establish_connection().await?;
+    cookies::init(&conn).await?;
+
+    match command {
+        DatabaseCommands::Reauth {
+            check,
+            from_file,
+            session,
+        } => {
+            let session = sessions::find(&conn, session.as_deref()).await?;
+            match from_file {
+                _ if check => check_session(&session).await?,
+                Some(path) => import_cookies(&session, &path).await?,
+                None => reauth(&session).await?,
+            }
         }
-        Commands::Courses {
-            command: CourseCommands::Fetch,
+        DatabaseCommands::Courses {
+            command:
+                CourseCommands::Fetch {
+                    allow_partial,
+                    dry_run,
+                },
         } => {
-            get_all_courses(&conn).await?;
+            get_all_courses(&conn, &Session::primary()?, allow_partial, dry_run).await?;
         }
-        Commands::Courses {
+        DatabaseCommands::Courses {
             command: CourseCommands::Stats,
         } => {
             display_stats(&conn).await?;
         }
-        Commands::Evals {
-            command: EvalCommands::Sids,
+        DatabaseCommands::Evals {
+            command: EvalCommands::Sids { dry_run },
         } => {
-            save_all_sids(&conn).await?;
+            let session = Arc::new(Session::primary()?);
+            let _renewal = renewal::spawn(session.clone());
+            save_all_sids(&conn, &session, dry_run).await?;
         }
-        Commands::Evals {
-            command: EvalCommands::Fetch { course: None },
+        DatabaseCommands::Evals {
+            command: EvalCommands::Fetch { filter, dry_run },
         } => {
-            let courses = query_as!(Course, "SELECT code, name, unit_id FROM courses WHERE code IN (SELECT course_code FROM sids WHERE sid NOT IN (SELECT sid FROM evaluations))")
-                .fetch_all(&conn)
-                .await?;
-
-            let m = MultiProgress::new();
-
-            let overall = m.add(common::progress_bar(courses.len() as u64));
-            overall.set_message("Courses");
-
-            for course in courses {
-                let sids = query!("SELECT sid, course_code FROM sids WHERE course_code = $1 AND sid NOT IN (SELECT sid FROM evaluations)", course.code)
-                    .fetch_all(&conn)
-                    .await?.into_iter().map(|s| s.sid).collect::<Vec<_>>();
-                let pb = m.insert_before(&overall, common::progress_bar(sids.len() as u64));
-                pb.println(format!("Found {} sids for {}", sids.len(), course.code));
-                let success = save_evals(&conn, &course, sids, &pb).await?;
-                if !success {
-                    reauth().await?;
-                }
-                pb.finish();
-                overall.inc(1);
-            }
-            overall.finish();
-            println!("Done");
+            let work = queue::pending(&conn, &filter).await?;
+            let sections = work.iter().map(|(_, sids)| sids.len()).sum::<usize>();
+            let failed = save_evals(&conn, &sessions::all(&conn).await?, work, dry_run).await?;
+            info!(sections, failed = failed.len(), "done");
         }
-        Commands::Evals {
-            command: EvalCommands::Fetch {
-                course: Some(course),
-            },
+        DatabaseCommands::Evals {
+            command: EvalCommands::Refresh { filter },
         } => {
-            let course = query_as!(
-                Course,
-                "SELECT code, name, unit_id FROM courses WHERE code = $1",
-                course
-            )
-            .fetch_one(&conn)
-            .await?;
-            let sids = query!("SELECT sid, course_code FROM sids WHERE course_code = $1 AND sid NOT IN (SELECT sid FROM evaluations)", course.code).fetch_all(&conn).await?.into_iter().map(|s| s.sid).collect::<Vec<_>>();
-            let pb = progress_bar(sids.len() as u64);
-            save_evals(&conn, &course, sids, &pb).await?;
-            pb.finish();
-            println!("Done");
+            let work = queue::stored(&conn, &filter).await?;
+            let sections = work.iter().map(|(_, sids)| sids.len()).sum::<usize>();
+            let failed = save_evals(&conn, &sessions::all(&conn).await?, work, false).await?;
+            info!(sections, failed = failed.len(), "done");
+        }
+        DatabaseCommands::Evals {
+            command:
+                EvalCommands::Enqueue {
+                    filter,
+                    refresh,
+                    priority,
+                },
+        } => {
+            jobs::enqueue(&conn, &filter, refresh, priority).await?;
+        }
+        DatabaseCommands::Worker { args } => {
+            jobs::work(&conn, &args).await?;
         }
-        Commands::Evals {
+        DatabaseCommands::Evals {
+            command: EvalCommands::Validate { quarantine },
+        } => {
+            validate::validate(&conn, quarantine).await?;
+        }
+        DatabaseCommands::Evals {
+            command: EvalCommands::CaptureFixture { sid, fixtures },
+        } => {
+            capture_fixture(&conn, sid, &fixtures).await?;
+        }
+        DatabaseCommands::Evals {
             command: EvalCommands::Stats,
         } => {
             let evals = query!("SELECT COUNT(*) FROM evaluations")
@@ -173,17 +393,52 @@ async fn main() -> Result<()> {
                 .count
                 .unwrap_or(0);
 
-            let sections =
-                query!("SELECT COUNT(*) FROM sids WHERE sid NOT IN (SELECT sid FROM evaluations)")
-                    .fetch_one(&conn)
-                    .await?
-                    .count
-                    .unwrap_or(0);
+            let sections = query!(
+                "
+                    SELECT COUNT(*) FROM sids
+                    WHERE sid NOT IN (SELECT sid FROM evaluations)
+                    AND sid NOT IN (SELECT sid FROM evaluations_quarantine)
+                "
+            )
+            .fetch_one(&conn)
+            .await?
+            .count
+            .unwrap_or(0);
+
+            let quarantined = query!("SELECT COUNT(*) FROM evaluations_quarantine")
+                .fetch_one(&conn)
+                .await?
+                .count
+                .unwrap_or(0);
 
             println!("{} evals", evals);
             println!("{} sections with no eval", sections);
+            let jobs = query!(
+                "
+                    SELECT
+                        COUNT(*) FILTER (WHERE attempts < $1) AS queued,
+                        COUNT(*) FILTER (WHERE attempts >= $1) AS failed
+                    FROM scrape_jobs
+                    WHERE done_at IS NULL
+                ",
+                jobs::MAX_ATTEMPTS
+            )
+            .fetch_one(&conn)
+            .await?;
+
+            println!("{} evals in quarantine", quarantined);
+            println!(
+                "{} scrape jobs queued, {} given up on",
+                jobs.queued.unwrap_or(0),
+                jobs.failed.unwrap_or(0)
+            );
         }
-        Commands::Serve { host } => {
+        DatabaseCommands::Serve { host, no_schedule } => {
+            let _scheduled = if no_schedule {
+                Vec::new()
+            } else {
+                scheduler::spawn(&conn)?
+            };
             let app = api::app(conn)?;
             let host = host.unwrap_or("0.0.0.0:3000".to_string());
             let listener = tokio::net::TcpListener::bind(&host).await?;
@@ -0,0 +1,159 @@
+use crate::auth::renewal;
+use crate::courses::get_all_courses;
+use crate::evaluations::queue::{self, EvalFilter};
+use crate::evaluations::save_evals;
+use crate::evaluations::sids::save_all_sids;
+use crate::sessions::{self, Session};
+use crate::settings;
+use crate::windows;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use cron::Schedule;
+use serde::Deserialize;
+use sqlx::{query, Pool, Postgres};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+/// Advisory lock key held while a scheduled run scrapes, so only one server does at a time.
+const SCRAPE_LOCK: i64 = 0x5e7_5c4a;
+
+/// A step of the scraping pipeline.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// `courses fetch`
+    Courses,
+    /// `evals sids`
+    Sids,
+    /// `evals fetch`
+    Evals,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Courses => "courses",
+            Stage::Sids => "sids",
+            Stage::Evals => "evals",
+        })
+    }
+}
+
+/// A `[[schedule]]` entry: pipeline stages to run in order whenever `cron` fires.
+#[derive(Deserialize, Debug)]
+pub struct ScheduleConfig {
+    /// Seconds, minutes, hours, day of month, month and day of week, in UTC
+    pub cron: String,
+    pub stages: Vec<Stage>,
+}
+
+/// Starts a background task for every `[[schedule]]` entry. Fails on invalid expressions
+/// rather than starting the server without them.
+pub fn spawn(conn: &Pool<Postgres>) -> Result<Vec<JoinHandle<()>>> {
+    let mut tasks = Vec::new();
+    for config in settings().schedule.iter().flatten() {
+        let schedule = Schedule::from_str(&config.cron)
+            .map_err(|e| anyhow!("Invalid cron expression {:?}: {e}", config.cron))?;
+        let stages = config.stages.clone();
+        let conn = conn.clone();
+        info!(
+            cron = config.cron,
+            "scheduled {}",
+            stages
+                .iter()
+                .map(Stage::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        tasks.push(tokio::spawn(async move {
+            // Looking for the next time afresh after every run skips the ones a long run
+            // went past, rather than starting again straight away to catch up
+            while let Some(next) = schedule.upcoming(Utc).next() {
+                sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+                if let Err(e) = run(&conn, &stages).await {
+                    error!(error = %format!("{e:#}"), "scheduled run failed");
+                }
+            }
+        }));
+    }
+    Ok(tasks)
+}
+
+/// Runs `stages` in order if no other server is scraping, stopping at the first failure.
+async fn run(conn: &Pool<Postgres>, stages: &[Stage]) -> Result<()> {
+    // A transaction-level lock lasts as long as the transaction, which stays open for the
+    // whole run. However the run ends, even by panicking or being cancelled, dropping the
+    // transaction rolls it back before its connection goes back to the pool, and if the
+    // server dies Postgres ends it when the connection drops.
+    let mut lock = conn.begin().await?;
+    let locked = query!("SELECT pg_try_advisory_xact_lock($1)", SCRAPE_LOCK)
+        .fetch_one(&mut *lock)
+        .await?
+        .pg_try_advisory_xact_lock
+        .unwrap_or(false);
+    if !locked {
+        info!("another server is scraping, skipping scheduled run");
+        return Ok(());
+    }
+
+    let res = run_stages(conn, stages).await;
+
+    lock.rollback().await?;
+    res
+}
+
+async fn run_stages(conn: &Pool<Postgres>, stages: &[Stage]) -> Result<()> {
+    for &stage in stages {
+        windows::wait_for_open().await;
+        let id = query!(
+            "INSERT INTO scrape_runs (stage) VALUES ($1) RETURNING id",
+            stage.to_string()
+        )
+        .fetch_one(conn)
+        .await?
+        .id;
+
+        let res = run_stage(conn, stage).await;
+        let (succeeded, detail) = match &res {
+            Ok(detail) => (true, detail.clone()),
+            Err(e) => (false, Some(format!("{e:#}"))),
+        };
+        query!(
+            "
+                UPDATE scrape_runs
+                SET finished_at = NOW(), succeeded = $2, detail = $3
+                WHERE id = $1
+            ",
+            id,
+            succeeded,
+            detail
+        )
+        .execute(conn)
+        .await?;
+        res?;
+    }
+    Ok(())
+}
+
+/// Runs one stage, returning anything worth keeping in the run history.
+async fn run_stage(conn: &Pool<Postgres>, stage: Stage) -> Result<Option<String>> {
+    match stage {
+        Stage::Courses => get_all_courses(conn, &Session::primary()?, false, false).await?,
+        Stage::Sids => {
+            let session = Arc::new(Session::primary()?);
+            let _renewal = renewal::spawn(session.clone());
+            save_all_sids(conn, &session, false).await?;
+        }
+        Stage::Evals => {
+            let work = queue::pending(conn, &EvalFilter::default()).await?;
+            let total = work.iter().map(|(_, sids)| sids.len()).sum::<usize>();
+            let failed = save_evals(conn, &sessions::all(conn).await?, work, false).await?;
+            return Ok(Some(format!("{} of {total} sections failed", failed.len())));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,180 @@
+use crate::auth::{self, AuthProvider};
+use crate::cookies::{self, Cookie, CookieStore, SessionCookies};
+use crate::settings;
+use crate::upstream::Upstream;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Name of the session built from the top-level settings.
+pub const DEFAULT_NAME: &str = "default";
+
+/// A set of credentials to scrape with, from `[[sessions]]` in the settings. Auth and
+/// cookie store settings left out fall back to the top-level ones.
+#[derive(Deserialize, Debug)]
+pub struct SessionConfig {
+    pub name: String,
+    pub cookie_store: Option<String>,
+    pub cookies_file: Option<String>,
+    pub auth_provider: Option<String>,
+    pub auth_file: Option<String>,
+    pub auth_command: Option<String>,
+    pub requests_per_minute: Option<u32>,
+}
+
+/// One logged-in identity on the SET site, with its own cookies, client and rate budget.
+pub struct Session {
+    pub name: String,
+    store: Arc<dyn CookieStore>,
+    cookies: Arc<SessionCookies>,
+    provider: Box<dyn AuthProvider>,
+    upstream: Upstream,
+    checks: Upstream,
+    /// When `renew` was last tried, whether or not it worked
+    last_renewal: Mutex<Option<Instant>>,
+}
+
+impl Session {
+    /// The session from the top-level settings, sharing the global cookie store.
+    pub fn primary() -> Result<Session> {
+        let upstream = Upstream::new()?;
+        Ok(Session {
+            name: DEFAULT_NAME.to_string(),
+            store: cookies::shared_store(),
+            cookies: cookies::session(),
+            provider: auth::provider()?,
+            checks: Upstream::new()?.uncached().sharing_pacer(&upstream),
+            upstream,
+            last_renewal: Mutex::new(None),
+        })
+    }
+
+    pub async fn open(config: &SessionConfig, conn: &Pool<Postgres>) -> Result<Session> {
+        let store = cookies::open_store(
+            config
+                .cookie_store
+                .as_deref()
+                .or(settings().cookie_store.as_deref()),
+            config.cookies_file.as_deref(),
+            conn,
+            &config.name,
+        )?;
+        let cookies = Arc::new(SessionCookies::new(store.load().await?));
+        let provider = auth::provider_from(
+            config
+                .auth_provider
+                .as_deref()
+                .or(settings().auth_provider.as_deref()),
+            config
+                .auth_file
+                .as_deref()
+                .or(settings().auth_file.as_deref()),
+            config
+                .auth_command
+                .as_deref()
+                .or(settings().auth_command.as_deref()),
+        )?;
+        let upstream = Upstream::with_cookies(cookies.clone())?
+            .paced(
+                config
+                    .requests_per_minute
+                    .or(settings().requests_per_minute),
+            )
+            .sticky_to(&config.name);
+        let checks = Upstream::with_cookies(cookies.clone())?
+            .uncached()
+            .sharing_pacer(&upstream)
+            .sticky_to(&config.name);
+        Ok(Session {
+            name: config.name.clone(),
+            store,
+            cookies,
+            provider,
+            upstream,
+            checks,
+            last_renewal: Mutex::new(None),
+        })
+    }
+
+    /// Answers the session's requests from the recordings of `base` in `dir`.
+    #[cfg(test)]
+    pub fn replaying(mut self, base: &str, dir: std::path::PathBuf) -> Session {
+        self.upstream = self.upstream.replaying(base, dir.clone());
+        self.checks = self.checks.replaying(base, dir);
+        self
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    /// Like `upstream`, but skipping the response cache, for session checks. Its requests
+    /// count against the same rate budget.
+    pub fn checks(&self) -> &Upstream {
+        &self.checks
+    }
+
+    pub fn cookies(&self) -> &Arc<SessionCookies> {
+        &self.cookies
+    }
+
+    /// Gets new cookies from the session's auth provider and swaps them in.
+    pub async fn renew(&self) -> Result<()> {
+        *self.last_renewal.lock().unwrap() = Some(Instant::now());
+        let cookies = self.provider.fetch().await?;
+        self.replace(cookies).await
+    }
+
+    /// Whether `renew` was tried less than `window` ago.
+    pub fn renewed_within(&self, window: Duration) -> bool {
+        self.last_renewal
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < window)
+    }
+
+    /// Saves `cookies` and swaps them into the session's live client.
+    pub async fn replace(&self, cookies: Vec<Cookie>) -> Result<()> {
+        self.store.save(&cookies).await?;
+        self.cookies.replace(cookies);
+        Ok(())
+    }
+
+    /// Writes cookies the server has updated since the last save back to the store.
+    pub async fn save(&self) -> Result<()> {
+        if self.cookies.take_dirty() {
+            self.store.save(&self.cookies.get()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Every configured session, or just the primary one when there's no `[[sessions]]` list.
+pub async fn all(conn: &Pool<Postgres>) -> Result<Vec<Arc<Session>>> {
+    match settings().sessions.as_deref() {
+        None | Some([]) => Ok(vec![Arc::new(Session::primary()?)]),
+        Some(configs) => {
+            let mut sessions = Vec::new();
+            for config in configs {
+                sessions.push(Arc::new(Session::open(config, conn).await?));
+            }
+            Ok(sessions)
+        }
+    }
+}
+
+/// The session called `name`, or the primary one without a name.
+pub async fn find(conn: &Pool<Postgres>, name: Option<&str>) -> Result<Session> {
+    let Some(name) = name.filter(|&name| name != DEFAULT_NAME) else {
+        return Session::primary();
+    };
+    let config = settings()
+        .sessions
+        .iter()
+        .flatten()
+        .find(|config| config.name == name)
+        .ok_or(anyhow!("No session named {name} in [[sessions]]"))?;
+    Session::open(config, conn).await
+}
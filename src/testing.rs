@@ -0,0 +1,117 @@
+//! Settings for tests. Settings are global, so every test in the process shares one
+//! `mock-auth` and one `mock-set`, started on first use.
+
+use crate::sessions::{Session, SessionConfig};
+use crate::{auth, upstream, Settings};
+use axum::Router;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{mpsc, LazyLock};
+use std::thread;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+pub const COOKIES_TOKEN: &str = "test-token";
+
+struct Servers {
+    auth: SocketAddr,
+    set: SocketAddr,
+}
+
+/// The mock servers run on their own thread so they outlive each test's runtime.
+static SERVERS: LazyLock<Servers> = LazyLock::new(|| {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        Runtime::new().unwrap().block_on(async {
+            let auth = serve(auth::mock::app(COOKIES_TOKEN, None).unwrap()).await;
+            let set = serve(upstream::mock::app(fixtures())).await;
+            tx.send(Servers { auth, set }).unwrap();
+            std::future::pending::<()>().await
+        })
+    });
+    rx.recv().unwrap()
+});
+
+async fn serve(app: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app.into_make_service()).await });
+    addr
+}
+
+pub fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Talks to the mock servers and keeps cookies in memory.
+pub fn settings() -> Settings {
+    Settings {
+        service_url: format!("http://{}", SERVERS.auth),
+        proxy_username: None,
+        proxy_password: None,
+        proxies: None,
+        proxy_strategy: None,
+        upstream_url: Some(format!("http://{}", SERVERS.set)),
+        cache_dir: None,
+        cookies_token: COOKIES_TOKEN.to_string(),
+        database_url: String::new(),
+        cookie_store: Some("memory".to_string()),
+        cookies_file: None,
+        auth_provider: None,
+        auth_file: None,
+        auth_command: None,
+        requests_per_minute: None,
+        sessions: None,
+        schedule: None,
+        scrape_windows: None,
+        log_file: None,
+    }
+}
+
+/// A session that gets its cookies from the mock cookie server. It isn't logged in until
+/// it's renewed. Its cookie file is removed when the returned directory is dropped.
+pub async fn session(name: &str) -> (Session, TempDir) {
+    open(name, "cookie-server", None).await
+}
+
+/// A session that fails if anything tries to renew it, for replaying cassettes.
+pub async fn offline_session(name: &str) -> (Session, TempDir) {
+    command_session(name, "false").await
+}
+
+/// A session that gets its cookies from the output of `command`.
+pub async fn command_session(name: &str, command: &str) -> (Session, TempDir) {
+    open(name, "command", Some(command)).await
+}
+
+/// Where a session opened by these helpers keeps its cookies.
+pub fn cookies_file(dir: &TempDir) -> PathBuf {
+    dir.path().join("cookies.json")
+}
+
+async fn open(name: &str, auth_provider: &str, auth_command: Option<&str>) -> (Session, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let session = Session::open(
+        &SessionConfig {
+            name: name.to_string(),
+            cookie_store: Some("file".to_string()),
+            cookies_file: Some(cookies_file(&dir).to_string_lossy().to_string()),
+            auth_provider: Some(auth_provider.to_string()),
+            auth_file: None,
+            auth_command: auth_command.map(str::to_string),
+            requests_per_minute: None,
+        },
+        &unused_pool(),
+    )
+    .await
+    .unwrap();
+    (session, dir)
+}
+
+/// A pool that never connects, for code that only needs one for the Postgres stores.
+pub fn unused_pool() -> Pool<Postgres> {
+    PgPoolOptions::new().connect_lazy_with(PgConnectOptions::new())
+}
@@ -0,0 +1,91 @@
+use crate::cookies::unix_now;
+use crate::upstream::{request_file, Body};
+use anyhow::Result;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How long responses from each endpoint stay fresh, along with text every good response
+/// contains so that login pages and errors are never cached. Evaluation pages aren't
+/// listed and always go to the network.
+const RULES: &[(&str, i64, &str)] = &[
+    ("/Search.aspx/GetUnits", 7 * DAY, "\"d\":"),
+    ("/Search.aspx/GetCourses", 7 * DAY, "\"d\":"),
+    ("/Search.aspx", DAY, "SETSummary.aspx?sid="),
+];
+
+/// Responses from the SET site kept on disk, one JSON file per distinct request.
+pub struct Cache {
+    dir: PathBuf,
+    base: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    stored_at: i64,
+    response: String,
+}
+
+fn rule(path: &str) -> Option<(i64, &'static str)> {
+    let path = path.split('?').next().unwrap_or_default();
+    RULES
+        .iter()
+        .find(|(endpoint, _, _)| path.ends_with(endpoint))
+        .map(|&(_, ttl, marker)| (ttl, marker))
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, base: &str) -> Cache {
+        Cache {
+            dir,
+            base: base.to_string(),
+        }
+    }
+
+    /// A saved response to the request that is still fresh, if there is one.
+    pub async fn get(&self, method: &Method, path: &str, body: &Body) -> Option<String> {
+        let (ttl, _) = rule(path)?;
+        let file = request_file(
+            &self.dir,
+            &self.base,
+            method,
+            path,
+            body.canonical().as_deref(),
+        );
+        let entry = serde_json::from_str::<Entry>(&fs::read_to_string(file).await.ok()?).ok()?;
+        (unix_now() - entry.stored_at < ttl).then_some(entry.response)
+    }
+
+    pub async fn put(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &Body,
+        response: &str,
+    ) -> Result<()> {
+        let Some((_, marker)) = rule(path) else {
+            return Ok(());
+        };
+        if !response.contains(marker) {
+            return Ok(());
+        }
+
+        let file = request_file(
+            &self.dir,
+            &self.base,
+            method,
+            path,
+            body.canonical().as_deref(),
+        );
+        let entry = Entry {
+            stored_at: unix_now(),
+            response: response.to_string(),
+        };
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(file, serde_json::to_string(&entry)?).await?;
+        Ok(())
+    }
+}
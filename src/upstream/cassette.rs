@@ -0,0 +1,97 @@
+use crate::upstream::{request_file, Body};
+use anyhow::Result;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs;
+
+/// A replayed request that was never recorded.
+#[derive(thiserror::Error, Debug)]
+#[error("No recorded response for {method} {path} in {file}")]
+pub struct MissingRecording {
+    method: Method,
+    path: String,
+    file: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Make real requests and save every response
+    Record,
+    /// Answer from saved responses only, without touching the network
+    Replay,
+}
+
+/// A directory of saved request/response pairs, one JSON file per distinct request.
+pub struct Cassette {
+    dir: PathBuf,
+    base: String,
+    mode: Mode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    path: String,
+    body: Option<String>,
+    status: u16,
+    response: String,
+}
+
+impl Cassette {
+    pub fn new(dir: PathBuf, base: &str, mode: Mode) -> Cassette {
+        Cassette {
+            dir,
+            base: base.to_string(),
+            mode,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub async fn replay(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &Body,
+    ) -> Result<(StatusCode, String)> {
+        let body = body.canonical();
+        let file = request_file(&self.dir, &self.base, method, path, body.as_deref());
+        let contents = fs::read_to_string(&file)
+            .await
+            .map_err(|_| MissingRecording {
+                method: method.clone(),
+                path: path.to_string(),
+                file: file.display().to_string(),
+            })?;
+        let interaction = serde_json::from_str::<Interaction>(&contents)?;
+        Ok((
+            StatusCode::from_u16(interaction.status)?,
+            interaction.response,
+        ))
+    }
+
+    pub async fn record(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &Body,
+        status: u16,
+        response: &str,
+    ) -> Result<()> {
+        let body = body.canonical();
+        let file = request_file(&self.dir, &self.base, method, path, body.as_deref());
+        let interaction = Interaction {
+            method: method.to_string(),
+            path: path.to_string(),
+            body,
+            status,
+            response: response.to_string(),
+        };
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(file, serde_json::to_string_pretty(&interaction)?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// What the SET site serves when the session has expired.
+const LOGIN_PAGE: &str = "<html><head><title>UC San Diego Single Sign-On</title></head><body><form id=\"login\"></form></body></html>";
+
+struct MockState {
+    fixtures: PathBuf,
+    requests: AtomicU64,
+}
+
+/// A stand-in for the SET site that serves fixture files, so the whole pipeline can run
+/// offline. The fixture directory holds:
+///
+/// - `GetUnits.json`
+/// - `GetCourses/<unit id>.json`
+/// - `Search/<course code>.html`, with anything but letters and digits in the code as `_`
+/// - `SETSummary/<sid>.html`
+///
+/// Requests without an `ASP.NET_SessionId` cookie get the login page, like the real site,
+/// and every response refreshes that cookie.
+pub fn app(fixtures: PathBuf) -> Router {
+    Router::new()
+        .route(
+            "/Modules/Evals/SET/Reports/Search.aspx/GetUnits",
+            post(get_units),
+        )
+        .route(
+            "/Modules/Evals/SET/Reports/Search.aspx/GetCourses",
+            post(get_courses),
+        )
+        .route("/Modules/Evals/SET/Reports/Search.aspx", post(search))
+        .route("/Modules/Evals/SET/Reports/SETSummary.aspx", get(summary))
+        .with_state(Arc::new(MockState {
+            fixtures,
+            requests: AtomicU64::new(0),
+        }))
+}
+
+impl MockState {
+    /// Serves `path` from the fixtures if the request carries a session.
+    async fn serve(&self, headers: &HeaderMap, path: PathBuf, content_type: &str) -> Response {
+        let has_session = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("ASP.NET_SessionId="));
+        if !has_session {
+            return Html(LOGIN_PAGE).into_response();
+        }
+
+        let n = self.requests.fetch_add(1, Ordering::Relaxed);
+        let set_cookie = format!("ASP.NET_SessionId=mock-refreshed-{n}; Max-Age=1800; Path=/");
+        match tokio::fs::read_to_string(self.fixtures.join(path)).await {
+            Ok(body) => (
+                [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::SET_COOKIE, set_cookie),
+                ],
+                body,
+            )
+                .into_response(),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+async fn get_units(State(state): State<Arc<MockState>>, headers: HeaderMap) -> Response {
+    state
+        .serve(&headers, PathBuf::from("GetUnits.json"), "application/json")
+        .await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CategoryRequest {
+    known_category_values: String,
+}
+
+async fn get_courses(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Json(body): Json<CategoryRequest>,
+) -> Response {
+    let Some(unit) = body.known_category_values.strip_prefix("Unit:") else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    state
+        .serve(
+            &headers,
+            PathBuf::from("GetCourses").join(format!("{}.json", file_name(unit))),
+            "application/json",
+        )
+        .await
+}
+
+async fn search(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let Some(code) = form
+        .get("ctl00$ctl00$ContentPlaceHolder1$EvalsContentPlaceHolder$CascadingDropDown4_ClientState")
+        .and_then(|state| state.split_once(":::"))
+        .map(|(code, _)| code)
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    state
+        .serve(
+            &headers,
+            PathBuf::from("Search").join(format!("{}.html", file_name(code))),
+            "text/html",
+        )
+        .await
+}
+
+#[derive(Deserialize)]
+struct SummaryQuery {
+    sid: i32,
+}
+
+async fn summary(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Query(query): Query<SummaryQuery>,
+) -> Response {
+    state
+        .serve(
+            &headers,
+            PathBuf::from("SETSummary").join(format!("{}.html", query.sid)),
+            "text/html",
+        )
+        .await
+}
+
+/// Keeps request values from escaping the fixture directory.
+fn file_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,330 @@
+mod cache;
+mod cassette;
+pub mod mock;
+mod pacer;
+mod proxy;
+
+pub use proxy::{ProxyConfig, ProxyStrategy};
+
+use crate::cookies::{self, SessionCookies};
+use crate::settings;
+use anyhow::{anyhow, bail, Result};
+use cache::Cache;
+pub use cassette::MissingRecording;
+use cassette::{Cassette, Mode};
+use clap::Args;
+use pacer::Pacer;
+use proxy::ProxyPool;
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tracing::{warn, Span};
+
+static OPTIONS: OnceCell<UpstreamArgs> = OnceCell::const_new();
+
+/// Command line options for how requests to the SET site are made.
+#[derive(Args, Debug, Default, Clone)]
+// Keeps the doc comment above from becoming the `--help` description of the whole CLI
+#[command(about = None)]
+pub struct UpstreamArgs {
+    /// Save every request and response the scraper makes to this directory
+    #[arg(long, global = true, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer requests from a directory saved with --record instead of the network
+    #[arg(long, global = true)]
+    pub replay: Option<PathBuf>,
+    /// Ignore `cache_dir` and send every request to the SET site
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+}
+
+pub fn init(options: UpstreamArgs) -> Result<()> {
+    if OPTIONS.set(options).is_err() {
+        bail!("Upstream options were already initialized");
+    }
+    Ok(())
+}
+
+/// The body of a request to the SET site.
+pub enum Body {
+    Empty,
+    Json(serde_json::Value),
+    Form(Vec<(String, String)>),
+}
+
+impl Body {
+    /// A stable text form of the body, used to tell requests apart.
+    fn canonical(&self) -> Option<String> {
+        match self {
+            Body::Empty => None,
+            Body::Json(json) => Some(json.to_string()),
+            Body::Form(form) => Some(
+                form.iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join("&"),
+            ),
+        }
+    }
+}
+
+/// Names the file for a request after its endpoint and a hash of everything that
+/// identifies it, including the site it went to, e.g. `SETSummary.aspx-1f0c…json`.
+fn request_file(
+    dir: &Path,
+    base: &str,
+    method: &Method,
+    path: &str,
+    body: Option<&str>,
+) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(base);
+    hasher.update(b"\0");
+    hasher.update(method.as_str());
+    hasher.update(b"\0");
+    hasher.update(path);
+    hasher.update(b"\0");
+    hasher.update(body.unwrap_or_default());
+    let hash = hasher
+        .finalize()
+        .iter()
+        .take(12)
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+
+    let endpoint = path
+        .split('?')
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap_or_default();
+    dir.join(format!("{endpoint}-{hash}.json"))
+}
+
+/// The SET site answered with an error status rather than a page.
+#[derive(thiserror::Error, Debug)]
+#[error("SET site responded with {0}")]
+pub struct StatusError(pub StatusCode);
+
+impl StatusError {
+    /// Whether the site turned the session down.
+    pub fn is_auth(&self) -> bool {
+        matches!(self.0, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+    }
+}
+
+/// Where the SET site lives unless `upstream_url` points somewhere else, such as `mock-set`.
+pub const DEFAULT_URL: &str = "https://academicaffairs.ucsd.edu";
+
+/// Every request the scraper makes to the SET site goes through here, spread over the
+/// configured proxies. Paths are relative to `upstream_url`.
+pub struct Upstream {
+    base: String,
+    pool: ProxyPool,
+    cassette: Option<Cassette>,
+    cache: Option<Cache>,
+    pacer: Option<Arc<Pacer>>,
+}
+
+impl Upstream {
+    pub fn new() -> Result<Upstream> {
+        Upstream::with_cookies(cookies::session())
+    }
+
+    /// Sends `cookies` instead of the session's.
+    pub fn with_cookies(cookies: Arc<SessionCookies>) -> Result<Upstream> {
+        let base = settings()
+            .upstream_url
+            .as_deref()
+            .unwrap_or(DEFAULT_URL)
+            .trim_end_matches('/')
+            .to_string();
+        Ok(Upstream {
+            pool: ProxyPool::new(cookies)?,
+            cassette: OPTIONS
+                .get()
+                .and_then(|options| match (&options.record, &options.replay) {
+                    (Some(dir), _) => Some(Cassette::new(dir.clone(), &base, Mode::Record)),
+                    (_, Some(dir)) => Some(Cassette::new(dir.clone(), &base, Mode::Replay)),
+                    _ => None,
+                }),
+            cache: settings()
+                .cache_dir
+                .as_ref()
+                .filter(|_| !OPTIONS.get().is_some_and(|options| options.no_cache))
+                .map(|dir| Cache::new(dir.into(), &base)),
+            base,
+            pacer: settings()
+                .requests_per_minute
+                .map(|requests| Arc::new(Pacer::per_minute(requests))),
+        })
+    }
+
+    /// Limits requests that reach the SET site to `requests_per_minute`, or lifts the
+    /// limit with `None`.
+    pub fn paced(mut self, requests_per_minute: Option<u32>) -> Upstream {
+        self.pacer = requests_per_minute.map(|requests| Arc::new(Pacer::per_minute(requests)));
+        self
+    }
+
+    /// Counts requests against `other`'s limit rather than a limit of its own.
+    pub fn sharing_pacer(mut self, other: &Upstream) -> Upstream {
+        self.pacer = other.pacer.clone();
+        self
+    }
+
+    /// Keys sticky proxy selection by `key`, usually the session's name, so sessions
+    /// don't all stick to the same proxy.
+    pub fn sticky_to(self, key: &str) -> Upstream {
+        self.pool.stick_to(key);
+        self
+    }
+
+    /// Answers requests from the recordings in `dir`, like `--replay`, as if the SET site
+    /// were at `base`.
+    #[cfg(test)]
+    pub fn replaying(mut self, base: &str, dir: PathBuf) -> Upstream {
+        self.base = base.to_string();
+        self.cassette = Some(Cassette::new(dir, base, Mode::Replay));
+        self
+    }
+
+    /// Whether responses come from a cassette rather than the SET site. Sessions can't be
+    /// renewed then, and don't need to be.
+    pub fn is_replaying(&self) -> bool {
+        self.cassette
+            .as_ref()
+            .is_some_and(|cassette| cassette.mode() == Mode::Replay)
+    }
+
+    /// Skips the response cache, for requests that must reach the SET site such as
+    /// session checks.
+    pub fn uncached(mut self) -> Upstream {
+        self.cache = None;
+        self
+    }
+
+    pub async fn get(&self, path: &str) -> Result<String> {
+        self.send(Method::GET, path, Body::Empty).await
+    }
+
+    pub async fn post_json(&self, path: &str, body: &impl Serialize) -> Result<String> {
+        self.send(Method::POST, path, Body::Json(serde_json::to_value(body)?))
+            .await
+    }
+
+    pub async fn post_form(&self, path: &str, form: &[(&str, String)]) -> Result<String> {
+        let form = form
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        self.send(Method::POST, path, Body::Form(form)).await
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Body) -> Result<String> {
+        let (status, text) = match &self.cassette {
+            Some(cassette) if cassette.mode() == Mode::Replay => {
+                cassette.replay(&method, path, &body).await?
+            }
+            Some(cassette) => {
+                let (status, text) = self.fetch(&method, path, &body).await?;
+                cassette
+                    .record(&method, path, &body, status.as_u16(), &text)
+                    .await?;
+                (status, text)
+            }
+            None => {
+                if let Some(cache) = &self.cache {
+                    if let Some(text) = cache.get(&method, path, &body).await {
+                        return Ok(text);
+                    }
+                }
+                let (status, text) = self.fetch(&method, path, &body).await?;
+                if let Some(cache) = self.cache.as_ref().filter(|_| status.is_success()) {
+                    if let Err(e) = cache.put(&method, path, &body, &text).await {
+                        warn!(path, error = %e, "could not cache response");
+                    }
+                }
+                (status, text)
+            }
+        };
+        if !status.is_success() {
+            return Err(StatusError(status).into());
+        }
+        Ok(text)
+    }
+
+    /// Sends a request through the pool, moving on to the next proxy if one fails.
+    async fn fetch(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &Body,
+    ) -> Result<(StatusCode, String)> {
+        let url = format!("{}{}", self.base, path);
+        let mut last_error = None;
+        for attempt in 0..self.pool.len() {
+            if attempt > 0 {
+                // Shows up on the `sid` span of evaluation requests
+                Span::current().record("retries", attempt);
+            }
+            if let Some(pacer) = &self.pacer {
+                pacer.wait().await;
+            }
+            let i = self.pool.pick();
+            let request = self.pool.client(i).request(method.clone(), &url);
+            let request = match body {
+                Body::Empty => request,
+                Body::Json(json) => request.json(json),
+                Body::Form(form) => request.form(form),
+            };
+
+            match request.send().await {
+                Ok(res) if !is_proxy_failure(res.status()) => {
+                    self.pool.report(i, true);
+                    return Ok((res.status(), res.text().await?));
+                }
+                Ok(res) => {
+                    self.pool.report(i, false);
+                    warn!(path, status = %res.status(), "proxy failed the request");
+                    last_error = Some(anyhow!("Proxy responded with {}", res.status()));
+                }
+                Err(e) => {
+                    self.pool.report(i, false);
+                    warn!(path, error = %e, "request failed");
+                    last_error = Some(e.into());
+                }
+            }
+        }
+        Err(last_error.unwrap_or(anyhow!("No proxies configured")))
+    }
+}
+
+/// Statuses that mean the proxy, rather than the SET site, could not handle the request.
+fn is_proxy_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::PROXY_AUTHENTICATION_REQUIRED
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_to_other_sites_are_kept_apart() {
+        let dir = Path::new("cache");
+        let file = |base| request_file(dir, base, &Method::GET, "/SETSummary.aspx?sid=1", None);
+        assert_eq!(file(DEFAULT_URL), file(DEFAULT_URL));
+        assert_ne!(file(DEFAULT_URL), file("http://127.0.0.1:5002"));
+        assert!(file(DEFAULT_URL)
+            .to_string_lossy()
+            .starts_with("cache/SETSummary.aspx-"));
+    }
+}
@@ -0,0 +1,25 @@
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+
+/// Spaces requests out evenly to stay within a budget of requests per minute.
+pub struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn per_minute(requests: u32) -> Pacer {
+        Pacer {
+            interval: Duration::from_secs(60) / requests.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the next request is allowed. Callers queue up behind each other.
+    pub async fn wait(&self) {
+        let mut next = self.next.lock().await;
+        sleep_until(*next).await;
+        *next = Instant::now().max(*next) + self.interval;
+    }
+}
@@ -0,0 +1,277 @@
+use crate::common;
+use crate::cookies::SessionCookies;
+use crate::settings;
+use anyhow::{bail, Result};
+use reqwest::{Client, Proxy, Url};
+use serde::Deserialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Consecutive failures after which a proxy is taken out of rotation.
+const MAX_FAILURES: u32 = 3;
+/// How long a failing proxy sits out before it is tried again.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProxyConfig {
+    /// `http://`, `https://` or `socks5://` URL
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyStrategy {
+    /// Use the next proxy for every request
+    #[default]
+    RoundRobin,
+    /// Keep using one proxy until it fails
+    Sticky,
+}
+
+struct Member {
+    name: String,
+    client: Client,
+    failures: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Member {
+    fn new(name: String, client: Client) -> Member {
+        Member {
+            name,
+            client,
+            failures: AtomicU32::new(0),
+            down_until: Mutex::new(None),
+        }
+    }
+
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| until <= now)
+    }
+}
+
+/// One client per proxy, or a single direct client when no proxies are configured.
+pub struct ProxyPool {
+    members: Vec<Member>,
+    strategy: ProxyStrategy,
+    cursor: AtomicUsize,
+}
+
+impl ProxyPool {
+    pub fn new(cookies: Arc<SessionCookies>) -> Result<ProxyPool> {
+        let mut members = Vec::new();
+        for config in proxies() {
+            let url = Url::parse(&config.url)?;
+            if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+                bail!("Unsupported proxy scheme in {}", config.url);
+            }
+            let mut proxy = Proxy::all(url.clone())?;
+            if let (Some(username), Some(password)) = (&config.username, &config.password) {
+                proxy = proxy.basic_auth(username, password);
+            }
+            members.push(Member::new(
+                format!(
+                    "{}://{}:{}",
+                    url.scheme(),
+                    url.host_str().unwrap_or_default(),
+                    url.port_or_known_default().unwrap_or_default()
+                ),
+                common::client(cookies.clone(), Some(proxy))?,
+            ));
+        }
+        if members.is_empty() {
+            members.push(Member::new(
+                "direct".to_string(),
+                common::client(cookies, None)?,
+            ));
+        }
+
+        Ok(ProxyPool::with_members(
+            members,
+            settings().proxy_strategy.unwrap_or_default(),
+        ))
+    }
+
+    fn with_members(members: Vec<Member>, strategy: ProxyStrategy) -> ProxyPool {
+        ProxyPool {
+            members,
+            strategy,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts a sticky pool on the proxy `key` maps to, so sessions with different keys
+    /// stick to different proxies instead of all piling onto the first.
+    pub fn stick_to(&self, key: &str) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.cursor.store(
+            hasher.finish() as usize % self.members.len(),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn client(&self, i: usize) -> &Client {
+        &self.members[i].client
+    }
+
+    /// Chooses the proxy for the next request, skipping ones that are cooling down. If every
+    /// proxy is down, the one due back soonest is used.
+    pub fn pick(&self) -> usize {
+        let now = Instant::now();
+        let start = match self.strategy {
+            ProxyStrategy::RoundRobin => self.cursor.fetch_add(1, Ordering::Relaxed),
+            ProxyStrategy::Sticky => self.cursor.load(Ordering::Relaxed),
+        };
+
+        let len = self.members.len();
+        let up = (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|&i| self.members[i].is_up(now));
+        let i = up.unwrap_or_else(|| {
+            (0..len)
+                .min_by_key(|&i| *self.members[i].down_until.lock().unwrap())
+                .unwrap_or(0)
+        });
+
+        if self.strategy == ProxyStrategy::Sticky {
+            self.cursor.store(i, Ordering::Relaxed);
+        }
+        i
+    }
+
+    /// Records how a request through proxy `i` went.
+    pub fn report(&self, i: usize, ok: bool) {
+        let member = &self.members[i];
+        if ok {
+            member.failures.store(0, Ordering::Relaxed);
+            *member.down_until.lock().unwrap() = None;
+            return;
+        }
+
+        if self.strategy == ProxyStrategy::Sticky {
+            self.cursor
+                .store((i + 1) % self.members.len(), Ordering::Relaxed);
+        }
+
+        let failures = member.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= MAX_FAILURES && self.members.len() > 1 {
+            warn!(
+                proxy = member.name,
+                failures, "resting proxy for {:?}", COOLDOWN
+            );
+            member.failures.store(0, Ordering::Relaxed);
+            *member.down_until.lock().unwrap() = Some(Instant::now() + COOLDOWN);
+        }
+    }
+}
+
+/// The configured proxies, or the cookie service's proxy when only the older
+/// `proxy_username` and `proxy_password` settings are given.
+fn proxies() -> Vec<ProxyConfig> {
+    if let Some(proxies) = &settings().proxies {
+        return proxies.clone();
+    }
+    match (&settings().proxy_username, &settings().proxy_password) {
+        (Some(username), Some(password)) => vec![ProxyConfig {
+            url: format!("{}:5000", settings().service_url),
+            username: Some(username.clone()),
+            password: Some(password.clone()),
+        }],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(len: usize, strategy: ProxyStrategy) -> ProxyPool {
+        let members = (0..len)
+            .map(|i| Member::new(format!("proxy-{i}"), Client::new()))
+            .collect();
+        ProxyPool::with_members(members, strategy)
+    }
+
+    #[test]
+    fn round_robin_rotates() {
+        let pool = pool(3, ProxyStrategy::RoundRobin);
+        let picks = (0..6).map(|_| pool.pick()).collect::<Vec<_>>();
+        assert_eq!(picks, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn sticky_moves_on_after_a_failure() {
+        let pool = pool(3, ProxyStrategy::Sticky);
+        assert_eq!([pool.pick(), pool.pick()], [0, 0]);
+        pool.report(0, false);
+        assert_eq!([pool.pick(), pool.pick()], [1, 1]);
+        pool.report(1, true);
+        assert_eq!(pool.pick(), 1);
+
+        // Wraps around from the last proxy
+        pool.report(1, false);
+        pool.report(2, false);
+        assert_eq!(pool.pick(), 0);
+    }
+
+    #[test]
+    fn sessions_spread_over_proxies() {
+        let picks = (0..8)
+            .map(|i| {
+                let pool = pool(2, ProxyStrategy::Sticky);
+                pool.stick_to(&format!("session-{i}"));
+                let pick = pool.pick();
+                assert_eq!(pool.pick(), pick);
+                pick
+            })
+            .collect::<Vec<_>>();
+        assert!(picks.contains(&0) && picks.contains(&1));
+    }
+
+    #[test]
+    fn failing_proxy_rests() {
+        let pool = pool(3, ProxyStrategy::RoundRobin);
+        for _ in 0..MAX_FAILURES {
+            pool.report(1, false);
+        }
+        let picks = (0..4).map(|_| pool.pick()).collect::<Vec<_>>();
+        assert!(!picks.contains(&1));
+
+        // A success brings it straight back
+        pool.report(1, true);
+        assert!((0..3).any(|_| pool.pick() == 1));
+    }
+
+    #[test]
+    fn all_down_uses_the_one_back_soonest() {
+        let pool = pool(3, ProxyStrategy::RoundRobin);
+        for i in [2, 0, 1] {
+            for _ in 0..MAX_FAILURES {
+                pool.report(i, false);
+            }
+        }
+        assert_eq!(pool.pick(), 2);
+    }
+
+    #[test]
+    fn lone_proxy_never_rests() {
+        let pool = pool(1, ProxyStrategy::RoundRobin);
+        for _ in 0..MAX_FAILURES * 2 {
+            pool.report(0, false);
+        }
+        assert!(pool.members[0].is_up(Instant::now()));
+    }
+}
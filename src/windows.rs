@@ -0,0 +1,205 @@
+use crate::settings;
+use anyhow::{anyhow, bail, Error, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::{America::Los_Angeles, Tz};
+use serde::Deserialize;
+use tracing::info;
+
+/// Scrape windows are in the SET site's local time.
+const TIMEZONE: Tz = Los_Angeles;
+
+/// A `[[scrape_windows]]` entry: a stretch of time on the given days when scraping is
+/// allowed. A window whose end is at or before its start runs past midnight into the
+/// next day, and one that ends when it starts lasts a full day.
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "WindowConfig")]
+pub struct Window {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+#[derive(Deserialize)]
+struct WindowConfig {
+    days: Vec<String>,
+    start: String,
+    end: String,
+}
+
+impl TryFrom<WindowConfig> for Window {
+    type Error = Error;
+
+    fn try_from(config: WindowConfig) -> Result<Window> {
+        let time = |s: &str| {
+            NaiveTime::parse_from_str(s, "%H:%M")
+                .map_err(|_| anyhow!("Invalid window time {s:?}, expected HH:MM"))
+        };
+        if config.days.is_empty() {
+            bail!("A scrape window needs at least one day");
+        }
+        Ok(Window {
+            days: config
+                .days
+                .iter()
+                .map(|day| day.parse().map_err(|_| anyhow!("Invalid day {day:?}")))
+                .collect::<Result<_>>()?,
+            start: time(&config.start)?,
+            end: time(&config.end)?,
+        })
+    }
+}
+
+impl Window {
+    /// The local start and end of this window if it opens on `day`'s date.
+    fn opening(&self, day: NaiveDateTime) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if !self.days.contains(&day.weekday()) {
+            return None;
+        }
+        let start = day.date().and_time(self.start);
+        let mut length = self.end - self.start;
+        if length <= Duration::zero() {
+            length += Duration::days(1);
+        }
+        Some((start, start + length))
+    }
+}
+
+/// When the window open at `now` closes, or nothing if none is open.
+fn open_until(windows: &[Window], now: NaiveDateTime) -> Option<NaiveDateTime> {
+    [now - Duration::days(1), now]
+        .into_iter()
+        .flat_map(|day| windows.iter().filter_map(move |w| w.opening(day)))
+        .filter(|&(start, end)| start <= now && now < end)
+        .map(|(_, end)| end)
+        .max()
+}
+
+/// When the next window after `now` opens.
+fn next_opening(windows: &[Window], now: NaiveDateTime) -> Option<NaiveDateTime> {
+    (0..=7)
+        .map(|days| now + Duration::days(days))
+        .flat_map(|day| windows.iter().filter_map(move |w| w.opening(day)))
+        .map(|(start, _)| start)
+        .filter(|&start| start > now)
+        .min()
+}
+
+fn local(time: NaiveDateTime) -> DateTime<Tz> {
+    // Times skipped when the clocks go forward don't exist, so read those as standard time
+    TIMEZONE
+        .from_local_datetime(&time)
+        .earliest()
+        .unwrap_or_else(|| TIMEZONE.from_utc_datetime(&(time + Duration::hours(8))))
+}
+
+/// When scraping may start again, or nothing if it's allowed now. Without any
+/// `[[scrape_windows]]`, scraping is always allowed.
+pub fn closed_until() -> Option<DateTime<Tz>> {
+    let windows = settings().scrape_windows.as_deref().unwrap_or_default();
+    if windows.is_empty() {
+        return None;
+    }
+    let now = Utc::now().with_timezone(&TIMEZONE).naive_local();
+    if open_until(windows, now).is_some() {
+        return None;
+    }
+    next_opening(windows, now).map(local)
+}
+
+/// Sleeps until a scrape window is open.
+pub async fn wait_for_open() {
+    let Some(opens) = closed_until() else {
+        return;
+    };
+    info!(
+        "outside the scrape windows, pausing until {}",
+        opens.format("%a %H:%M %Z")
+    );
+    let left = opens.with_timezone(&Utc) - Utc::now();
+    tokio::time::sleep(left.to_std().unwrap_or_default()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn window(days: &[&str], start: &str, end: &str) -> Window {
+        Window::try_from(WindowConfig {
+            days: days.iter().map(|day| day.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+        })
+        .unwrap()
+    }
+
+    /// 2026-10-19 is a Monday.
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+    }
+
+    #[test]
+    fn overnight_window_runs_past_midnight() {
+        let windows = [window(
+            &["Mon", "Tue", "Wed", "Thu", "Fri"],
+            "22:00",
+            "07:00",
+        )];
+        assert_eq!(open_until(&windows, at(19, "23:30")), Some(at(20, "07:00")));
+        assert_eq!(open_until(&windows, at(20, "06:59")), Some(at(20, "07:00")));
+        assert_eq!(open_until(&windows, at(20, "07:00")), None);
+        assert_eq!(
+            next_opening(&windows, at(20, "12:00")),
+            Some(at(20, "22:00"))
+        );
+        // Friday night's window is the last one until Monday
+        assert_eq!(open_until(&windows, at(24, "03:00")), Some(at(24, "07:00")));
+        assert_eq!(
+            next_opening(&windows, at(24, "08:00")),
+            Some(at(26, "22:00"))
+        );
+    }
+
+    #[test]
+    fn full_day_and_weekend_windows() {
+        let windows = [
+            window(&["Sat", "Sun"], "00:00", "00:00"),
+            window(&["Mon"], "01:00", "05:00"),
+        ];
+        assert_eq!(open_until(&windows, at(25, "15:00")), Some(at(26, "00:00")));
+        assert_eq!(
+            next_opening(&windows, at(26, "00:00")),
+            Some(at(26, "01:00"))
+        );
+        assert_eq!(open_until(&windows, at(26, "00:30")), None);
+        assert_eq!(open_until(&windows, at(21, "12:00")), None);
+        assert_eq!(
+            next_opening(&windows, at(21, "12:00")),
+            Some(at(24, "00:00"))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_windows() {
+        assert!(Window::try_from(WindowConfig {
+            days: vec!["Someday".to_string()],
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+        })
+        .is_err());
+        assert!(Window::try_from(WindowConfig {
+            days: vec!["Sat".to_string()],
+            start: "10pm".to_string(),
+            end: "07:00".to_string(),
+        })
+        .is_err());
+        assert!(Window::try_from(WindowConfig {
+            days: Vec::new(),
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+        })
+        .is_err());
+    }
+}